@@ -0,0 +1,45 @@
+#!/usr/bin/env python3
+"""Speech recognizer for the bot's voice commands, using a local Vosk model.
+
+Reads 16kHz mono signed 16 bit little endian audio on stdin and prints every
+recognized utterance on its own line. The grammar only knows the voice
+commands, which keeps even the small models fast and accurate.
+
+    pip install vosk
+    SPEECH_COMMAND="python3 scripts/vosk-listen.py /path/to/vosk-model-small-en-us bot"
+
+The wake word given here must match `SPEECH_WAKE_WORD`, "bot" by default.
+"""
+
+import json
+import sys
+
+from vosk import KaldiRecognizer, Model, SetLogLevel
+
+RATE = 16000
+CHUNK = RATE // 10 * 2  # 100ms
+
+
+def main():
+    if len(sys.argv) < 2:
+        sys.exit("usage: vosk-listen.py <model dir> [wake word]")
+
+    wake = " ".join(sys.argv[2:]) or "bot"
+    grammar = ["%s %s" % (wake, command) for command in ("ready", "status", "cancel")]
+
+    SetLogLevel(-1)
+    recognizer = KaldiRecognizer(Model(sys.argv[1]), RATE, json.dumps(grammar + ["[unk]"]))
+
+    while True:
+        chunk = sys.stdin.buffer.read(CHUNK)
+        if not chunk:
+            break
+
+        if recognizer.AcceptWaveform(chunk):
+            text = json.loads(recognizer.Result()).get("text", "")
+            if text:
+                print(text, flush=True)
+
+
+if __name__ == "__main__":
+    main()
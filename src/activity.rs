@@ -0,0 +1,179 @@
+//! Talk time of the users in the voice channels the bot is listening in.
+//!
+//! Discord only sends voice packets while someone talks, each one carrying
+//! 20ms of audio, so talk time is the packet count of a user times 20ms.
+//! Packets are tagged with an SSRC, mapped to a user by the speaking and
+//! connect events, which can arrive after the first packets: those are kept
+//! per SSRC until the mapping is known.
+
+use chrono::{DateTime, Utc};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Audio carried by a single voice packet.
+pub const FRAME: Duration = Duration::from_millis(20);
+
+/// Activity since the bot joined a voice channel.
+pub struct Session {
+    pub channel_id: ChannelId,
+    pub started: DateTime<Utc>,
+    users: HashMap<u32, UserId>,
+    frames: HashMap<UserId, u32>,
+    /// Frames of SSRCs not yet mapped to a user.
+    unmapped: HashMap<u32, u32>,
+}
+
+impl Session {
+    fn new(channel_id: ChannelId) -> Self {
+        Self {
+            channel_id,
+            started: Utc::now(),
+            users: HashMap::new(),
+            frames: HashMap::new(),
+            unmapped: HashMap::new(),
+        }
+    }
+
+    /// Talk time per user, most talkative first.
+    pub fn talk_time(&self) -> Vec<(UserId, Duration)> {
+        let mut talk: Vec<(UserId, Duration)> = self
+            .frames
+            .iter()
+            .map(|(user, frames)| (*user, FRAME * *frames))
+            .collect();
+        talk.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        talk
+    }
+}
+
+#[derive(Default)]
+pub struct VoiceActivity {
+    sessions: HashMap<GuildId, Session>,
+}
+
+impl TypeMapKey for VoiceActivity {
+    type Value = Arc<Mutex<VoiceActivity>>;
+}
+
+impl VoiceActivity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking a channel, dropping the previous session of the guild.
+    pub fn start_session(&mut self, guild_id: GuildId, channel_id: ChannelId) {
+        self.sessions.insert(guild_id, Session::new(channel_id));
+    }
+
+    pub fn end_session(&mut self, guild_id: GuildId) -> Option<Session> {
+        self.sessions.remove(&guild_id)
+    }
+
+    pub fn session(&self, guild_id: GuildId) -> Option<&Session> {
+        self.sessions.get(&guild_id)
+    }
+
+    /// Maps `ssrc` to a user, crediting them the frames received before.
+    pub fn map_ssrc(&mut self, guild_id: GuildId, ssrc: u32, user_id: UserId) {
+        if let Some(session) = self.sessions.get_mut(&guild_id) {
+            session.users.insert(ssrc, user_id);
+            if let Some(frames) = session.unmapped.remove(&ssrc) {
+                *session.frames.entry(user_id).or_insert(0) += frames;
+            }
+        }
+    }
+
+    pub fn user(&self, guild_id: GuildId, ssrc: u32) -> Option<UserId> {
+        self.sessions.get(&guild_id)?.users.get(&ssrc).cloned()
+    }
+
+    /// Counts a voice packet of `ssrc`.
+    pub fn packet(&mut self, guild_id: GuildId, ssrc: u32) {
+        if let Some(session) = self.sessions.get_mut(&guild_id) {
+            let frames = match session.users.get(&ssrc) {
+                Some(user_id) => session.frames.entry(*user_id).or_insert(0),
+                None => session.unmapped.entry(ssrc).or_insert(0),
+            };
+            *frames += 1;
+        }
+    }
+
+    /// Forgets the SSRCs of a user who left, their talk time is kept.
+    pub fn disconnect(&mut self, guild_id: GuildId, user_id: UserId) {
+        if let Some(session) = self.sessions.get_mut(&guild_id) {
+            session.users.retain(|_, user| *user != user_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId(1);
+
+    #[test]
+    fn counts_frames_per_user() {
+        let mut activity = VoiceActivity::new();
+        activity.start_session(GUILD, ChannelId(2));
+        activity.map_ssrc(GUILD, 10, UserId(100));
+        activity.map_ssrc(GUILD, 11, UserId(101));
+
+        for _ in 0..50 {
+            activity.packet(GUILD, 10);
+        }
+        for _ in 0..100 {
+            activity.packet(GUILD, 11);
+        }
+
+        let talk = activity.session(GUILD).unwrap().talk_time();
+        assert_eq!(
+            talk,
+            vec![
+                (UserId(101), Duration::from_secs(2)),
+                (UserId(100), Duration::from_secs(1))
+            ]
+        );
+    }
+
+    #[test]
+    fn credits_packets_received_before_the_mapping() {
+        let mut activity = VoiceActivity::new();
+        activity.start_session(GUILD, ChannelId(2));
+        for _ in 0..5 {
+            activity.packet(GUILD, 10);
+        }
+        assert!(activity.session(GUILD).unwrap().talk_time().is_empty());
+
+        activity.map_ssrc(GUILD, 10, UserId(100));
+        activity.packet(GUILD, 10);
+
+        assert_eq!(
+            activity.session(GUILD).unwrap().talk_time(),
+            vec![(UserId(100), FRAME * 6)]
+        );
+    }
+
+    #[test]
+    fn sessions_reset_when_moving_channels() {
+        let mut activity = VoiceActivity::new();
+        activity.packet(GUILD, 10);
+        assert!(activity.session(GUILD).is_none());
+
+        activity.start_session(GUILD, ChannelId(2));
+        activity.map_ssrc(GUILD, 10, UserId(100));
+        activity.packet(GUILD, 10);
+        activity.disconnect(GUILD, UserId(100));
+        assert_eq!(activity.user(GUILD, 10), None);
+        assert_eq!(activity.session(GUILD).unwrap().talk_time().len(), 1);
+
+        activity.start_session(GUILD, ChannelId(3));
+        assert!(activity.session(GUILD).unwrap().talk_time().is_empty());
+        assert!(activity.end_session(GUILD).is_some());
+        assert!(activity.session(GUILD).is_none());
+    }
+}
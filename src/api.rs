@@ -0,0 +1,333 @@
+//! JSON admin API under `/api`, for operators to inspect and control the bot
+//! without Discord.
+//!
+//! Every request needs `Authorization: Bearer <http.admin_token>`, and the
+//! API answers 404 when no token is configured. Ids are strings, as they
+//! don't fit in a JavaScript number.
+//!
+//! - `GET /api/guilds`
+//! - `GET /api/roll-calls`, `DELETE /api/roll-calls/<guild>`
+//! - `GET /api/voice`
+//! - `POST /api/guilds/<guild>/say` with `{"text": "..."}`, answering 202 as
+//!   the speech is fetched in the background
+//! - `POST /api/shards/<shard>/restart`
+
+use crate::commands::voice;
+use crate::config;
+use crate::context::{self, BotError};
+use crate::settings;
+use crate::{RollCallManager, ShardManagerContainer, VoiceManager};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serenity::cache::Cache;
+use serenity::client::bridge::gateway::ShardId;
+use serenity::http::Http;
+use serenity::model::id::GuildId;
+use serenity::prelude::*;
+use std::io::Read;
+use std::sync::Arc;
+use tiny_http::{Method, Request};
+
+/// Longest text `say` speaks.
+const MAX_SAY_LEN: usize = 500;
+
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    Guilds,
+    RollCalls,
+    CancelRollCall(GuildId),
+    Voice,
+    Say(GuildId),
+    RestartShard(u64),
+}
+
+impl Route {
+    pub fn parse(method: &Method, path: &str) -> Option<Self> {
+        let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let route = match (method, parts.as_slice()) {
+            (Method::Get, ["api", "guilds"]) => Route::Guilds,
+            (Method::Get, ["api", "roll-calls"]) => Route::RollCalls,
+            (Method::Delete, ["api", "roll-calls", guild]) => {
+                Route::CancelRollCall(GuildId(guild.parse().ok()?))
+            }
+            (Method::Get, ["api", "voice"]) => Route::Voice,
+            (Method::Post, ["api", "guilds", guild, "say"]) => {
+                Route::Say(GuildId(guild.parse().ok()?))
+            }
+            (Method::Post, ["api", "shards", shard, "restart"]) => {
+                Route::RestartShard(shard.parse().ok()?)
+            }
+            _ => return None,
+        };
+
+        Some(route)
+    }
+}
+
+/// Whether an `Authorization` header has the token.
+pub fn is_authorized(header: Option<&str>, token: &str) -> bool {
+    match header.and_then(|h| h.strip_prefix("Bearer ")) {
+        Some(given) => same_token(given.trim(), token),
+        None => false,
+    }
+}
+
+/// Compares all of the tokens, so the time taken doesn't tell how much
+/// matched.
+pub fn same_token(given: &str, token: &str) -> bool {
+    let (given, token) = (given.as_bytes(), token.as_bytes());
+
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The `Authorization` header of a request.
+pub fn authorization(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str().to_string())
+}
+
+#[derive(Deserialize)]
+struct SayBody {
+    text: String,
+}
+
+/// What the API reaches into.
+pub struct Api<'a> {
+    pub data: &'a Arc<RwLock<ShareMap>>,
+    pub cache: &'a RwLock<Cache>,
+    pub http: &'a Http,
+}
+
+impl Api<'_> {
+    /// Answers a request to `/api`, with its status and JSON body.
+    pub fn handle(&self, request: &mut Request) -> (u16, Value) {
+        let token = match config::current().http.admin_token.clone() {
+            Some(token) => token,
+            None => return error(404, "not found"),
+        };
+
+        if !is_authorized(authorization(request).as_deref(), &token) {
+            return error(401, "unauthorized");
+        }
+
+        let path = request.url().split('?').next().unwrap_or("").to_string();
+        let route = match Route::parse(request.method(), &path) {
+            Some(route) => route,
+            None => return error(404, "not found"),
+        };
+        info!("Admin API: {} {}", request.method(), path);
+
+        match route {
+            Route::Guilds => (200, self.guilds()),
+            Route::RollCalls => self.roll_calls(),
+            Route::CancelRollCall(guild_id) => self.cancel_roll_call(guild_id),
+            Route::Voice => self.voice(),
+            Route::Say(guild_id) => self.say(guild_id, request),
+            Route::RestartShard(shard) => self.restart_shard(shard),
+        }
+    }
+
+    fn guilds(&self) -> Value {
+        let cache = self.cache.read();
+        let mut guilds: Vec<Value> = cache
+            .guilds
+            .values()
+            .map(|guild| {
+                let guild = guild.read();
+                json!({
+                    "id": guild.id.to_string(),
+                    "name": guild.name,
+                    "members": guild.member_count,
+                })
+            })
+            .collect();
+        guilds.sort_by_key(|guild| guild["id"].as_str().map(String::from));
+
+        Value::Array(guilds)
+    }
+
+    fn roll_calls(&self) -> (u16, Value) {
+        let manager_lock = match context::state::<RollCallManager>(self.data) {
+            Ok(manager_lock) => manager_lock,
+            Err(why) => return bot_error(why),
+        };
+        let manager = manager_lock.lock();
+        let mut roll_calls: Vec<Value> = manager
+            .list
+            .values()
+            .map(|rc| {
+                json!({
+                    "guild_id": rc.guild_id.to_string(),
+                    "started_by": rc.call_by.to_string(),
+                    "requested": rc.requested,
+                    "joined": rc.joined.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                    "missing": rc.lack(),
+                })
+            })
+            .collect();
+        roll_calls.sort_by_key(|rc| rc["guild_id"].as_str().map(String::from));
+
+        (200, Value::Array(roll_calls))
+    }
+
+    fn cancel_roll_call(&self, guild_id: GuildId) -> (u16, Value) {
+        let cancelled = match context::state::<RollCallManager>(self.data) {
+            Ok(manager_lock) => manager_lock.lock().cancel_running_call_for(guild_id),
+            Err(why) => return bot_error(why),
+        };
+        if !cancelled {
+            return error(404, &BotError::NoRollCall.to_string());
+        }
+
+        if let Some(channel_id) = settings::of(self.data, guild_id).announce_channel {
+            crate::check_sending_message(
+                channel_id.say(self.http, "@here Roll-Call cancelled. :'("),
+            );
+        }
+
+        (200, json!({ "cancelled": guild_id.to_string() }))
+    }
+
+    fn voice(&self) -> (u16, Value) {
+        let voice_lock = match context::state::<VoiceManager>(self.data) {
+            Ok(voice_lock) => voice_lock,
+            Err(why) => return bot_error(why),
+        };
+        let guild_ids: Vec<GuildId> = self.cache.read().guilds.keys().copied().collect();
+        let voice = voice_lock.lock();
+        let mut connections: Vec<Value> = guild_ids
+            .into_iter()
+            .filter_map(|guild_id| {
+                let handler = voice.get(guild_id)?;
+                Some(json!({
+                    "guild_id": guild_id.to_string(),
+                    "channel_id": handler.channel_id.map(|c| c.to_string()),
+                    "muted": handler.self_mute,
+                    "deafened": handler.self_deaf,
+                }))
+            })
+            .collect();
+        connections.sort_by_key(|c| c["guild_id"].as_str().map(String::from));
+
+        (200, Value::Array(connections))
+    }
+
+    fn say(&self, guild_id: GuildId, request: &mut Request) -> (u16, Value) {
+        let mut body = String::new();
+        let read = request
+            .as_reader()
+            .take(MAX_SAY_LEN as u64 * 4 + 64)
+            .read_to_string(&mut body);
+        let text = match read
+            .ok()
+            .and_then(|_| serde_json::from_str::<SayBody>(&body).ok())
+        {
+            Some(body) => body.text.trim().to_string(),
+            None => return error(400, "expected {\"text\": \"...\"}"),
+        };
+        if text.is_empty() || text.chars().count() > MAX_SAY_LEN {
+            return error(
+                400,
+                &format!("text must be 1 to {} characters", MAX_SAY_LEN),
+            );
+        }
+
+        let in_voice = context::state::<VoiceManager>(self.data)
+            .map(|voice_lock| voice_lock.lock().get(guild_id).is_some());
+        match in_voice {
+            Ok(true) => (),
+            Ok(false) => return bot_error(BotError::NotInVoice),
+            Err(why) => return bot_error(why),
+        }
+
+        // Fetching the speech takes a round trip to the provider, which would
+        // hold up every other request of the server.
+        let data = Arc::clone(self.data);
+        std::thread::spawn(move || {
+            if let Err(why) = voice::say(&data, guild_id, text) {
+                warn!("[{}] Unable to say the admin API's text: {}", guild_id, why);
+            }
+        });
+
+        (202, json!({ "queued": true }))
+    }
+
+    fn restart_shard(&self, shard: u64) -> (u16, Value) {
+        let shard_manager = match context::state::<ShardManagerContainer>(self.data) {
+            Ok(shard_manager) => shard_manager,
+            Err(why) => return bot_error(why),
+        };
+        let mut manager = shard_manager.lock();
+        if !manager.has(ShardId(shard)) {
+            return error(404, &format!("no shard {}", shard));
+        }
+
+        manager.restart(ShardId(shard));
+
+        (200, json!({ "restarted": shard }))
+    }
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+fn bot_error(why: BotError) -> (u16, Value) {
+    let status = match why {
+        BotError::MissingState(_) => 500,
+        BotError::NoRollCall => 404,
+        _ => 409,
+    };
+
+    error(status, &why.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_routes() {
+        assert_eq!(
+            Route::parse(&Method::Get, "/api/guilds"),
+            Some(Route::Guilds)
+        );
+        assert_eq!(
+            Route::parse(&Method::Delete, "/api/roll-calls/42"),
+            Some(Route::CancelRollCall(GuildId(42)))
+        );
+        assert_eq!(
+            Route::parse(&Method::Post, "/api/guilds/42/say/"),
+            Some(Route::Say(GuildId(42)))
+        );
+        assert_eq!(
+            Route::parse(&Method::Post, "/api/shards/1/restart"),
+            Some(Route::RestartShard(1))
+        );
+        assert_eq!(Route::parse(&Method::Post, "/api/guilds"), None);
+        assert_eq!(
+            Route::parse(&Method::Delete, "/api/roll-calls/general"),
+            None
+        );
+        assert_eq!(Route::parse(&Method::Get, "/api/shards"), None);
+    }
+
+    #[test]
+    fn checks_the_bearer_token() {
+        let token = "0123456789abcdef";
+        assert!(is_authorized(Some("Bearer 0123456789abcdef"), token));
+        assert!(!is_authorized(Some("Bearer 0123456789abcdeF"), token));
+        assert!(!is_authorized(Some("Bearer 0123456789"), token));
+        assert!(!is_authorized(Some("0123456789abcdef"), token));
+        assert!(!is_authorized(None, token));
+    }
+}
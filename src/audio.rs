@@ -0,0 +1,628 @@
+//! Conversion of WAV, OGG and MP3 audio into the PCM format Discord voice expects.
+//!
+//! Discord plays 48 kHz, stereo, signed 16 bit little endian PCM. Every audio
+//! source the bot plays (TTS providers, local files) goes through here so the
+//! voice handler can always be fed with `pcm(true, ...)`.
+
+use hound::{SampleFormat, WavIntoSamples, WavReader};
+use sample::interpolate::{self, Converter};
+use sample::signal::{self, FromIterator, UntilExhausted};
+use sample::{ring_buffer, Sample, Signal};
+use serenity::voice::{pcm, AudioSource};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::path::Path;
+
+/// Sample rate Discord voice plays at.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Channel count Discord voice plays at.
+pub const CHANNELS: u16 = 2;
+
+/// Amount of frames the sinc interpolator looks around each output frame.
+const SINC_DEPTH: usize = 100;
+
+pub type StereoFrame = [f64; 2];
+
+#[derive(Debug)]
+pub enum AudioError {
+    Io(std::io::Error),
+    Wav(hound::Error),
+    Vorbis(lewton::VorbisError),
+    Mp3(minimp3::Error),
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioError::Io(e) => write!(f, "Unable to read audio: {}", e),
+            AudioError::Wav(e) => write!(f, "Invalid WAV audio: {}", e),
+            AudioError::Vorbis(e) => write!(f, "Invalid OGG audio: {}", e),
+            AudioError::Mp3(e) => write!(f, "Invalid MP3 audio: {}", e),
+            AudioError::Unsupported(reason) => write!(f, "Unsupported audio: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl From<std::io::Error> for AudioError {
+    fn from(e: std::io::Error) -> Self {
+        AudioError::Io(e)
+    }
+}
+
+impl From<hound::Error> for AudioError {
+    fn from(e: hound::Error) -> Self {
+        AudioError::Wav(e)
+    }
+}
+
+impl From<lewton::VorbisError> for AudioError {
+    fn from(e: lewton::VorbisError) -> Self {
+        AudioError::Vorbis(e)
+    }
+}
+
+impl From<minimp3::Error> for AudioError {
+    fn from(e: minimp3::Error) -> Self {
+        AudioError::Mp3(e)
+    }
+}
+
+/// Audio file formats that can be decoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Wav,
+    Ogg,
+    Mp3,
+}
+
+impl Format {
+    pub const EXTENSIONS: [&'static str; 3] = ["wav", "ogg", "mp3"];
+
+    /// Guesses the format from the file extension, case insensitively.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Format> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "wav" => Some(Format::Wav),
+            "ogg" => Some(Format::Ogg),
+            "mp3" => Some(Format::Mp3),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Wav => "wav",
+            Format::Ogg => "ogg",
+            Format::Mp3 => "mp3",
+        }
+    }
+}
+
+/// Decodes a WAV stream of any sample rate, bit depth and channel count into
+/// interleaved 48 kHz stereo i16 samples.
+pub fn wav_to_pcm<R: Read>(reader: R) -> Result<Vec<i16>, AudioError> {
+    let reader = WavReader::new(reader)?;
+    let sample_rate = reader.spec().sample_rate;
+    let frames = WavFrames::new(reader)?.collect::<Result<Vec<_>, _>>()?;
+
+    Ok(resample(frames, sample_rate))
+}
+
+/// Decodes an OGG Vorbis stream into interleaved 48 kHz stereo i16 samples.
+pub fn ogg_to_pcm<R: Read + Seek>(reader: R) -> Result<Vec<i16>, AudioError> {
+    let mut ogg = lewton::inside_ogg::OggStreamReader::new(reader)?;
+    let channels = usize::from(ogg.ident_hdr.audio_channels);
+    let sample_rate = ogg.ident_hdr.audio_sample_rate;
+
+    let mut frames = Vec::new();
+    while let Some(packet) = ogg.read_dec_packet_itl()? {
+        frames.extend(interleaved_to_frames(&packet, channels)?);
+    }
+
+    Ok(resample(frames, sample_rate))
+}
+
+/// Decodes an MP3 stream into interleaved 48 kHz stereo i16 samples.
+pub fn mp3_to_pcm<R: Read>(reader: R) -> Result<Vec<i16>, AudioError> {
+    let mut decoder = minimp3::Decoder::new(reader);
+    let mut sample_rate = None;
+
+    let mut frames = Vec::new();
+    loop {
+        match decoder.next_frame() {
+            Ok(frame) => {
+                sample_rate.get_or_insert(frame.sample_rate);
+                frames.extend(interleaved_to_frames(&frame.data, frame.channels)?);
+            }
+            Err(minimp3::Error::Eof) => break,
+            Err(minimp3::Error::SkippedData) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    match sample_rate {
+        Some(rate) if rate > 0 => Ok(resample(frames, rate as u32)),
+        _ => Err(AudioError::Unsupported("MP3 has no audio frames")),
+    }
+}
+
+/// Decodes a WAV, OGG or MP3 file on disk, picking the decoder from its extension.
+pub fn file_to_pcm<P: AsRef<Path>>(path: P) -> Result<Vec<i16>, AudioError> {
+    let format = Format::from_path(&path).ok_or(AudioError::Unsupported(
+        "file extension must be wav, ogg or mp3",
+    ))?;
+    let file = BufReader::new(File::open(path)?);
+
+    match format {
+        Format::Wav => wav_to_pcm(file),
+        Format::Ogg => ogg_to_pcm(file),
+        Format::Mp3 => mp3_to_pcm(file),
+    }
+}
+
+fn interleaved_to_frames(samples: &[i16], channels: usize) -> Result<Vec<StereoFrame>, AudioError> {
+    if channels == 0 {
+        return Err(AudioError::Unsupported("audio has no channels"));
+    }
+
+    Ok(samples
+        .chunks_exact(channels)
+        .map(|frame| {
+            let frame: Vec<f64> = frame.iter().map(|s| s.to_sample::<f64>()).collect();
+            to_stereo(&frame)
+        })
+        .collect())
+}
+
+/// Converts a WAV stream lazily, only reading from `reader` as the returned
+/// `Read` is consumed.
+///
+/// Only the WAV header is read up front, so playback of a slow source (like
+/// a TTS http response) can start as soon as its first chunk arrives. A
+/// decoding error half way through ends the stream early instead of failing.
+pub fn wav_stream<R: Read>(
+    reader: R,
+) -> Result<PcmStream<impl Iterator<Item = StereoFrame>>, AudioError> {
+    let reader = WavReader::new(reader)?;
+    let sample_rate = reader.spec().sample_rate;
+    let frames = WavFrames::new(reader)?.map_while(|frame| match frame {
+        Ok(frame) => Some(frame),
+        Err(e) => {
+            warn!("Audio stream ended early: {}", e);
+
+            None
+        }
+    });
+
+    Ok(PcmStream::new(Resampled::new(frames, sample_rate)))
+}
+
+enum WavSamples<R: Read> {
+    Int(WavIntoSamples<R, i32>, f64),
+    Float(WavIntoSamples<R, f32>),
+}
+
+/// Lazily decoded WAV audio as normalized stereo `f64` frames.
+///
+/// Mono is duplicated to both channels; for more than two channels only the
+/// front left and front right channels are kept.
+struct WavFrames<R: Read> {
+    samples: WavSamples<R>,
+    channels: usize,
+    frame: Vec<f64>,
+}
+
+impl<R: Read> WavFrames<R> {
+    fn new(reader: WavReader<R>) -> Result<Self, AudioError> {
+        let spec = reader.spec();
+        if spec.channels == 0 {
+            return Err(AudioError::Unsupported("WAV has no channels"));
+        }
+
+        let samples = match spec.sample_format {
+            SampleFormat::Float => WavSamples::Float(reader.into_samples()),
+            SampleFormat::Int => {
+                if spec.bits_per_sample == 0 || spec.bits_per_sample > 32 {
+                    return Err(AudioError::Unsupported(
+                        "bit depth must be between 1 and 32",
+                    ));
+                }
+
+                let scale = f64::from(1u32 << (spec.bits_per_sample - 1));
+                WavSamples::Int(reader.into_samples(), scale)
+            }
+        };
+
+        Ok(Self {
+            samples,
+            channels: usize::from(spec.channels),
+            frame: Vec::with_capacity(usize::from(spec.channels)),
+        })
+    }
+
+    fn next_sample(&mut self) -> Option<Result<f64, hound::Error>> {
+        match &mut self.samples {
+            WavSamples::Int(samples, scale) => {
+                let scale = *scale;
+                samples.next().map(|s| s.map(|v| f64::from(v) / scale))
+            }
+            WavSamples::Float(samples) => samples.next().map(|s| s.map(f64::from)),
+        }
+    }
+}
+
+impl<R: Read> Iterator for WavFrames<R> {
+    type Item = Result<StereoFrame, hound::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.frame.clear();
+        while self.frame.len() < self.channels {
+            match self.next_sample()? {
+                Ok(sample) => self.frame.push(sample),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        Some(Ok(to_stereo(&self.frame)))
+    }
+}
+
+fn to_stereo(channels: &[f64]) -> StereoFrame {
+    match channels {
+        [mono] => [*mono, *mono],
+        [left, right, ..] => [*left, *right],
+        [] => [0.0, 0.0],
+    }
+}
+
+type SincConverter<I> =
+    UntilExhausted<Converter<FromIterator<I>, interpolate::Sinc<[StereoFrame; SINC_DEPTH]>>>;
+
+/// Stereo frames converted to 48 kHz as they are pulled.
+enum Resampled<I: Iterator<Item = StereoFrame>> {
+    Direct(I),
+    Sinc(Box<SincConverter<I>>),
+}
+
+impl<I: Iterator<Item = StereoFrame>> Resampled<I> {
+    fn new(frames: I, from_hz: u32) -> Self {
+        if from_hz == SAMPLE_RATE {
+            return Resampled::Direct(frames);
+        }
+
+        let sinc = interpolate::Sinc::new(ring_buffer::Fixed::from([[0.0; 2]; SINC_DEPTH]));
+        let converted = signal::from_iter(frames).from_hz_to_hz(
+            sinc,
+            f64::from(from_hz),
+            f64::from(SAMPLE_RATE),
+        );
+
+        Resampled::Sinc(Box::new(converted.until_exhausted()))
+    }
+}
+
+impl<I: Iterator<Item = StereoFrame>> Iterator for Resampled<I> {
+    type Item = StereoFrame;
+
+    fn next(&mut self) -> Option<StereoFrame> {
+        match self {
+            Resampled::Direct(frames) => frames.next(),
+            Resampled::Sinc(frames) => frames.next(),
+        }
+    }
+}
+
+/// Converts stereo frames at `from_hz` to interleaved 48 kHz i16 samples
+/// using sinc interpolation.
+pub fn resample(frames: Vec<StereoFrame>, from_hz: u32) -> Vec<i16> {
+    Resampled::new(frames.into_iter(), from_hz)
+        .flat_map(|frame| frame.to_vec())
+        .map(|s| s.to_sample::<i16>())
+        .collect()
+}
+
+/// Amount of frames converted at a time by `PcmStream`, 20ms of audio.
+const STREAM_CHUNK_FRAMES: usize = 960;
+
+/// `Read` adaptor serving 48 kHz stereo i16 little endian PCM bytes,
+/// converting the next chunk of frames only once the previous one was read.
+pub struct PcmStream<I: Iterator<Item = StereoFrame>> {
+    frames: I,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl<I: Iterator<Item = StereoFrame>> PcmStream<I> {
+    fn new(frames: I) -> Self {
+        Self {
+            frames,
+            chunk: Vec::with_capacity(STREAM_CHUNK_FRAMES * 4),
+            position: 0,
+        }
+    }
+
+    fn fill_chunk(&mut self) {
+        self.chunk.clear();
+        self.position = 0;
+        for frame in self.frames.by_ref().take(STREAM_CHUNK_FRAMES) {
+            for s in frame.iter() {
+                self.chunk
+                    .extend_from_slice(&s.to_sample::<i16>().to_le_bytes());
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = StereoFrame>> Read for PcmStream<I> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.chunk.len() {
+            self.fill_chunk();
+        }
+
+        let available = &self.chunk[self.position..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// Serializes samples as little endian bytes, the layout `pcm` reads.
+pub fn pcm_to_bytes(samples: &[i16]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|s| s.to_le_bytes().to_vec())
+        .collect()
+}
+
+/// Wraps converted samples in a source ready for `Handler::play`.
+pub fn source(samples: &[i16]) -> Box<dyn AudioSource> {
+    pcm(true, Cursor::new(pcm_to_bytes(samples)))
+}
+
+/// Loads a local audio file as a playable source.
+///
+/// WAV files are decoded as they play, other formats are decoded up front.
+pub fn file_source<P: AsRef<Path>>(path: P) -> Result<Box<dyn AudioSource>, AudioError> {
+    if Format::from_path(&path) == Some(Format::Wav) {
+        let file = BufReader::new(File::open(path)?);
+
+        return Ok(pcm(true, wav_stream(file)?));
+    }
+
+    Ok(source(&file_to_pcm(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::{WavSpec, WavWriter};
+    use std::f64::consts::PI;
+
+    const FREQUENCY: f64 = 440.0;
+    const AMPLITUDE: f64 = 0.5;
+
+    fn sine(spec: WavSpec, seconds: f64) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        {
+            let mut writer = WavWriter::new(&mut buffer, spec).unwrap();
+            let frames = (f64::from(spec.sample_rate) * seconds) as usize;
+            for n in 0..frames {
+                let t = n as f64 / f64::from(spec.sample_rate);
+                let value = AMPLITUDE * (2.0 * PI * FREQUENCY * t).sin();
+                for _ in 0..spec.channels {
+                    match spec.sample_format {
+                        SampleFormat::Float => writer.write_sample(value as f32).unwrap(),
+                        SampleFormat::Int => {
+                            let scale = f64::from(1u32 << (spec.bits_per_sample - 1));
+                            writer.write_sample((value * scale) as i32).unwrap()
+                        }
+                    }
+                }
+            }
+            writer.finalize().unwrap();
+        }
+
+        buffer.into_inner()
+    }
+
+    fn spec(sample_rate: u32, channels: u16, bits: u16, format: SampleFormat) -> WavSpec {
+        WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: bits,
+            sample_format: format,
+        }
+    }
+
+    fn left(samples: &[i16]) -> Vec<i16> {
+        samples.iter().step_by(2).cloned().collect()
+    }
+
+    fn peak(samples: &[i16]) -> f64 {
+        let max = samples.iter().map(|s| i32::from(*s).abs()).max().unwrap();
+
+        f64::from(max) / f64::from(i16::MAX)
+    }
+
+    /// Counts sign changes, ignoring quantization noise around zero.
+    fn zero_crossings(samples: &[i16]) -> usize {
+        let threshold = i16::MAX / 10;
+        let mut positive = None;
+        let mut crossings = 0;
+        for &s in samples {
+            let side = if s > threshold {
+                Some(true)
+            } else if s < -threshold {
+                Some(false)
+            } else {
+                continue;
+            };
+
+            if positive.is_some() && positive != side {
+                crossings += 1;
+            }
+            positive = side;
+        }
+
+        crossings
+    }
+
+    fn assert_converted(samples: &[i16], seconds: f64) {
+        let expected_frames = f64::from(SAMPLE_RATE) * seconds;
+        let frames = (samples.len() / 2) as f64;
+        assert!(
+            (frames - expected_frames).abs() <= SINC_DEPTH as f64,
+            "expected about {} frames, got {}",
+            expected_frames,
+            frames
+        );
+
+        assert!((peak(samples) - AMPLITUDE).abs() < 0.05);
+
+        let crossings = zero_crossings(&left(samples)) as f64;
+        let expected_crossings = 2.0 * FREQUENCY * seconds;
+        assert!((crossings - expected_crossings).abs() <= 4.0);
+    }
+
+    #[test]
+    fn passes_through_discord_format() {
+        let wav = sine(spec(48_000, 2, 16, SampleFormat::Int), 1.0);
+        let samples = wav_to_pcm(Cursor::new(wav)).unwrap();
+
+        assert_eq!(samples.len(), 96_000);
+        assert_converted(&samples, 1.0);
+    }
+
+    #[test]
+    fn upsamples_mono_to_stereo() {
+        let wav = sine(spec(24_000, 1, 16, SampleFormat::Int), 1.0);
+        let samples = wav_to_pcm(Cursor::new(wav)).unwrap();
+
+        assert_converted(&samples, 1.0);
+        for frame in samples.chunks(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+    }
+
+    #[test]
+    fn downsamples_multichannel() {
+        let wav = sine(spec(96_000, 6, 24, SampleFormat::Int), 0.5);
+        let samples = wav_to_pcm(Cursor::new(wav)).unwrap();
+
+        assert_converted(&samples, 0.5);
+    }
+
+    #[test]
+    fn converts_other_bit_depths() {
+        for (bits, format) in &[
+            (8, SampleFormat::Int),
+            (32, SampleFormat::Int),
+            (32, SampleFormat::Float),
+        ] {
+            let wav = sine(spec(44_100, 2, *bits, *format), 0.5);
+            let samples = wav_to_pcm(Cursor::new(wav)).unwrap();
+
+            assert_converted(&samples, 0.5);
+        }
+    }
+
+    #[test]
+    fn streams_same_samples_as_buffered() {
+        let wav = sine(spec(22_050, 1, 16, SampleFormat::Int), 0.5);
+        let buffered = wav_to_pcm(Cursor::new(wav.clone())).unwrap();
+
+        let mut stream = wav_stream(Cursor::new(wav)).unwrap();
+        let mut streamed = Vec::new();
+        let mut buf = [0u8; 333];
+        loop {
+            let read = stream.read(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            streamed.extend_from_slice(&buf[..read]);
+        }
+
+        assert_eq!(streamed, pcm_to_bytes(&buffered));
+    }
+
+    #[test]
+    fn streams_before_input_is_complete() {
+        let wav = sine(spec(24_000, 1, 16, SampleFormat::Int), 1.0);
+        let total = wav.len() as u64;
+        let mut stream = wav_stream(Cursor::new(wav).take(total / 4)).unwrap();
+
+        let mut first_chunk = vec![0u8; STREAM_CHUNK_FRAMES * 4];
+        stream.read_exact(&mut first_chunk).unwrap();
+        assert!(first_chunk.iter().any(|b| *b != 0));
+    }
+
+    /// Decodes a fixture in `resources/test`: mono 44.1 kHz tones of a
+    /// single spectral line, written by hand so they stay tiny.
+    fn fixture(name: &str) -> Vec<i16> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("resources/test")
+            .join(name);
+
+        file_to_pcm(path).unwrap()
+    }
+
+    fn assert_decoded(samples: &[i16], seconds: f64) {
+        let expected_frames = f64::from(SAMPLE_RATE) * seconds;
+        let frames = (samples.len() / 2) as f64;
+        assert!(
+            (frames - expected_frames).abs() <= SINC_DEPTH as f64,
+            "expected about {} frames, got {}",
+            expected_frames,
+            frames
+        );
+
+        assert!((peak(samples) - AMPLITUDE).abs() < 0.05);
+        for frame in samples.chunks(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+    }
+
+    #[test]
+    fn decodes_ogg_vorbis() {
+        // 172 blocks of 128 samples, the first one only primes the decoder
+        let samples = fixture("tone.ogg");
+
+        assert_decoded(&samples, 172.0 * 128.0 / 44_100.0);
+    }
+
+    #[test]
+    fn decodes_mp3() {
+        // 20 frames of 1152 samples
+        let samples = fixture("tone.mp3");
+
+        assert_decoded(&samples, 20.0 * 1152.0 / 44_100.0);
+    }
+
+    #[test]
+    fn rejects_invalid_wav() {
+        let result = wav_to_pcm(Cursor::new(b"definitely not a wav".to_vec()));
+
+        assert!(matches!(result, Err(AudioError::Wav(_))));
+    }
+
+    #[test]
+    fn detects_format_from_extension() {
+        assert_eq!(Format::from_path("sounds/boom.WAV"), Some(Format::Wav));
+        assert_eq!(Format::from_path("sounds/boom.ogg"), Some(Format::Ogg));
+        assert_eq!(Format::from_path("boom.mp3"), Some(Format::Mp3));
+        assert_eq!(Format::from_path("boom.gif"), None);
+        assert_eq!(Format::from_path("boom"), None);
+    }
+
+    #[test]
+    fn serializes_little_endian() {
+        assert_eq!(pcm_to_bytes(&[1, -2]), vec![1, 0, 0xfe, 0xff]);
+    }
+}
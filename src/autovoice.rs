@@ -0,0 +1,252 @@
+//! Joining and leaving voice on its own.
+//!
+//! The bot follows the users and roles set in the `voice` section of the
+//! config into voice, leaves channels it has been alone in for its
+//! `idle_timeout` and rejoins its channels after reconnecting.
+
+use crate::commands::voice;
+use crate::config;
+
+use serenity::cache::Cache;
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How often the idle check runs.
+const TICK: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct AutoVoice {
+    pub follow_users: HashSet<UserId>,
+    pub follow_roles: HashSet<RoleId>,
+    /// `None` never leaves.
+    pub idle_timeout: Option<Duration>,
+    /// Channel the bot is in, per guild.
+    channels: HashMap<GuildId, ChannelId>,
+    alone_since: HashMap<GuildId, Instant>,
+}
+
+impl TypeMapKey for AutoVoice {
+    type Value = Arc<Mutex<AutoVoice>>;
+}
+
+impl AutoVoice {
+    pub fn new(idle_timeout: Option<Duration>) -> Self {
+        Self {
+            idle_timeout,
+            ..Self::default()
+        }
+    }
+
+    /// Follows and leaves as set in the `voice` section of the config.
+    pub fn from_config(voice: &config::Voice) -> Self {
+        let mut auto = Self::new(Some(DEFAULT_IDLE_TIMEOUT));
+        auto.configure(voice);
+
+        auto
+    }
+
+    /// Applies the users, roles and idle timeout of the `voice` section,
+    /// keeping the channels the bot is in.
+    pub fn configure(&mut self, voice: &config::Voice) {
+        if let Ok(idle_timeout) = voice.idle_timeout() {
+            self.idle_timeout = idle_timeout;
+        }
+        self.follow_users = voice.follow_users.iter().cloned().map(UserId).collect();
+        self.follow_roles = voice.follow_roles.iter().cloned().map(RoleId).collect();
+    }
+
+    /// Whether a user, with the given roles, is followed into voice.
+    pub fn follows(&self, user_id: UserId, roles: &[RoleId]) -> bool {
+        self.follow_users.contains(&user_id) || roles.iter().any(|r| self.follow_roles.contains(r))
+    }
+
+    pub fn joined(&mut self, guild_id: GuildId, channel_id: ChannelId) {
+        self.channels.insert(guild_id, channel_id);
+        self.alone_since.remove(&guild_id);
+    }
+
+    pub fn left(&mut self, guild_id: GuildId) {
+        self.channels.remove(&guild_id);
+        self.alone_since.remove(&guild_id);
+    }
+
+    pub fn channel(&self, guild_id: GuildId) -> Option<ChannelId> {
+        self.channels.get(&guild_id).cloned()
+    }
+
+    pub fn channels(&self) -> Vec<(GuildId, ChannelId)> {
+        self.channels.iter().map(|(g, c)| (*g, *c)).collect()
+    }
+
+    /// Tracks whether the bot has company in its channel.
+    pub fn set_alone(&mut self, guild_id: GuildId, alone: bool, now: Instant) {
+        if !alone {
+            self.alone_since.remove(&guild_id);
+        } else if self.channels.contains_key(&guild_id) {
+            self.alone_since.entry(guild_id).or_insert(now);
+        }
+    }
+
+    /// Guilds the bot has been alone in for longer than the idle timeout.
+    pub fn idle(&self, now: Instant) -> Vec<GuildId> {
+        let timeout = match self.idle_timeout {
+            Some(timeout) => timeout,
+            None => return Vec::new(),
+        };
+
+        self.alone_since
+            .iter()
+            .filter(|(_, since)| now.duration_since(**since) >= timeout)
+            .map(|(guild_id, _)| *guild_id)
+            .collect()
+    }
+
+    /// Starts a thread leaving the channels the bot is alone in. Other bots
+    /// don't count as company.
+    pub fn spawn_idle_check(
+        auto: Arc<Mutex<AutoVoice>>,
+        data: Arc<RwLock<ShareMap>>,
+        cache: Arc<RwLock<Cache>>,
+        http: Arc<Http>,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(TICK);
+
+            for guild_id in check_idle(&auto, &cache, Instant::now()) {
+                info!("[{}] Leaving voice, alone for too long", guild_id);
+                if let Err(why) = voice::disconnect(&data, &http, guild_id) {
+                    debug!("[{}] Leaving voice: {}", guild_id, why);
+                }
+            }
+        })
+    }
+}
+
+/// Notes which channels the bot is alone in, returning the guilds it has
+/// been alone in for too long.
+fn check_idle(auto: &Mutex<AutoVoice>, cache: &RwLock<Cache>, now: Instant) -> Vec<GuildId> {
+    // not locked while reading the cache, nor across `set_alone`.
+    let channels = auto.lock().channels();
+    for (guild_id, channel_id) in channels {
+        let alone = humans_in(cache, guild_id, channel_id) == 0;
+        auto.lock().set_alone(guild_id, alone, now);
+    }
+
+    auto.lock().idle(now)
+}
+
+/// Users other than bots, the bot itself included, connected to a voice
+/// channel.
+pub fn humans_in(cache: &RwLock<Cache>, guild_id: GuildId, channel_id: ChannelId) -> usize {
+    let cache = cache.read();
+    let guild = match cache.guild(guild_id) {
+        Some(guild) => guild,
+        None => return 0,
+    };
+
+    let guild = guild.read();
+    guild
+        .voice_states
+        .values()
+        .filter(|state| state.channel_id == Some(channel_id) && state.user_id != cache.user.id)
+        .filter(|state| match cache.user(state.user_id) {
+            Some(user) => !user.read().bot,
+            None => true,
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId(1);
+
+    #[test]
+    fn follows_users_and_roles() {
+        let mut auto = AutoVoice::new(None);
+        auto.follow_users.insert(UserId(10));
+        auto.follow_roles.insert(RoleId(20));
+
+        assert!(auto.follows(UserId(10), &[]));
+        assert!(auto.follows(UserId(11), &[RoleId(21), RoleId(20)]));
+        assert!(!auto.follows(UserId(11), &[RoleId(21)]));
+    }
+
+    #[test]
+    fn applies_the_voice_config() {
+        let mut auto = AutoVoice::from_config(&config::Voice::default());
+        assert_eq!(auto.idle_timeout, Some(DEFAULT_IDLE_TIMEOUT));
+        auto.joined(GUILD, ChannelId(2));
+
+        auto.configure(&config::Voice {
+            idle_timeout: String::from("off"),
+            follow_users: vec![10],
+            follow_roles: vec![20],
+        });
+
+        assert_eq!(auto.idle_timeout, None);
+        assert!(auto.follows(UserId(10), &[]));
+        assert!(auto.follows(UserId(11), &[RoleId(20)]));
+        assert_eq!(auto.channel(GUILD), Some(ChannelId(2)));
+    }
+
+    #[test]
+    fn idles_after_being_alone_for_the_timeout() {
+        let mut auto = AutoVoice::new(Some(Duration::from_secs(60)));
+        let start = Instant::now();
+
+        // not in voice, nothing to leave
+        auto.set_alone(GUILD, true, start);
+        assert!(auto.idle(start + Duration::from_secs(120)).is_empty());
+
+        auto.joined(GUILD, ChannelId(2));
+        auto.set_alone(GUILD, true, start);
+        auto.set_alone(GUILD, true, start + Duration::from_secs(30));
+        assert!(auto.idle(start + Duration::from_secs(59)).is_empty());
+        assert_eq!(auto.idle(start + Duration::from_secs(60)), vec![GUILD]);
+
+        // company resets the timer
+        auto.set_alone(GUILD, false, start + Duration::from_secs(61));
+        auto.set_alone(GUILD, true, start + Duration::from_secs(90));
+        assert!(auto.idle(start + Duration::from_secs(120)).is_empty());
+
+        auto.left(GUILD);
+        assert!(auto.idle(start + Duration::from_secs(600)).is_empty());
+        assert_eq!(auto.channel(GUILD), None);
+    }
+
+    #[test]
+    fn checks_idle_channels() {
+        let mut auto = AutoVoice::new(Some(Duration::from_secs(60)));
+        auto.joined(GUILD, ChannelId(2));
+        let auto = Mutex::new(auto);
+        let cache = RwLock::new(Cache::default());
+        let start = Instant::now();
+
+        assert!(check_idle(&auto, &cache, start).is_empty());
+        assert_eq!(
+            check_idle(&auto, &cache, start + Duration::from_secs(60)),
+            vec![GUILD]
+        );
+        assert!(auto.try_lock().is_some());
+    }
+
+    #[test]
+    fn never_idles_without_a_timeout() {
+        let mut auto = AutoVoice::new(None);
+        let start = Instant::now();
+        auto.joined(GUILD, ChannelId(2));
+        auto.set_alone(GUILD, true, start);
+
+        assert!(auto.idle(start + Duration::from_secs(86_400)).is_empty());
+        assert_eq!(auto.channels(), vec![(GUILD, ChannelId(2))]);
+    }
+}
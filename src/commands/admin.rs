@@ -0,0 +1,220 @@
+use crate as bot;
+use bot::autovoice::AutoVoice;
+use bot::commands::voice;
+use bot::config::{self, Config};
+use bot::context::{self, BotError};
+use bot::countdown::{AnnouncementPolicy, CountdownManager};
+use bot::settings::SettingsStore;
+use bot::shutdown;
+use bot::speech::SpeechManager;
+use bot::ShardManagerContainer;
+
+use serenity::client::bridge::gateway::ShardMessenger;
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::model::gateway::Activity;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::sync::Arc;
+
+/// Discord rejects messages longer than this.
+const MAX_MESSAGE_LEN: usize = 2000;
+
+/// Joins lines into as few messages as fit.
+fn messages(lines: &[String]) -> Vec<String> {
+    let mut messages = vec![String::new()];
+    for line in lines {
+        let current = messages.last_mut().expect("At least one message");
+        if !current.is_empty() && current.len() + line.len() + 1 > MAX_MESSAGE_LEN {
+            messages.push(line.clone());
+        } else {
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(line);
+        }
+    }
+
+    messages
+}
+
+#[command("guilds")]
+#[owners_only]
+#[description("Lists the guilds the bot is in, with their member counts.")]
+fn admin_guilds(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let mut guilds: Vec<(String, GuildId, u64)> = ctx
+        .cache
+        .read()
+        .guilds
+        .values()
+        .map(|guild| {
+            let guild = guild.read();
+            (guild.name.clone(), guild.id, guild.member_count)
+        })
+        .collect();
+    guilds.sort();
+
+    let mut lines = vec![format!("**{} guilds**", guilds.len())];
+    lines.extend(
+        guilds
+            .iter()
+            .map(|(name, id, members)| format!("{} (`{}`): {} members", name, id, members)),
+    );
+    for message in messages(&lines) {
+        bot::check_sending_message(msg.channel_id.say(&ctx.http, message));
+    }
+
+    Ok(())
+}
+
+#[command("leave")]
+#[owners_only]
+#[num_args(1)]
+#[description("Makes the bot leave a guild.")]
+#[example("leave 123456789012345678")]
+fn admin_leave(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = GuildId(
+        args.parse::<u64>()
+            .map_err(|_| "Which guild? Use its id, see `admin guilds`.")?,
+    );
+    let name = ctx
+        .cache
+        .read()
+        .guild(guild_id)
+        .map(|guild| guild.read().name.clone())
+        .ok_or("The bot isn't in that guild.")?;
+
+    match voice::disconnect(&ctx.data, &ctx.http, guild_id) {
+        Ok(()) | Err(BotError::NotInVoice) => (),
+        Err(why) => warn!("[{}] Unable to leave voice: {}", guild_id, why),
+    }
+    guild_id
+        .leave(&ctx.http)
+        .map_err(|_| "Unable to leave that guild.")?;
+
+    info!("Left guild {} ({}) for {}", name, guild_id, msg.author.id);
+    bot::check_sending_message(msg.reply(&ctx, format!("Left {}.", name)));
+
+    Ok(())
+}
+
+#[command("presence")]
+#[owners_only]
+#[description("Sets what the bot is playing, optionally with its status first: `online`, `idle`, `dnd` or `invisible`. Without text it's cleared.")]
+#[usage("[status] [text]")]
+#[example("presence dnd Rally tonight at 21:00")]
+fn admin_presence(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+    let status = match args.current().map(str::to_lowercase).as_deref() {
+        Some("online") => Some(OnlineStatus::Online),
+        Some("idle") => Some(OnlineStatus::Idle),
+        Some("dnd") => Some(OnlineStatus::DoNotDisturb),
+        Some("invisible") => Some(OnlineStatus::Invisible),
+        _ => None,
+    };
+    if status.is_some() {
+        args.advance();
+    }
+
+    let text = args.rest().trim();
+    let activity = if text.is_empty() {
+        None
+    } else {
+        Some(Activity::playing(text))
+    };
+
+    let shard_manager = context::state::<ShardManagerContainer>(&ctx.data)?;
+    let manager = shard_manager.lock();
+    for runner in manager.runners.lock().values() {
+        ShardMessenger::new(runner.runner_tx.clone())
+            .set_presence(activity.clone(), status.unwrap_or(OnlineStatus::Online));
+    }
+
+    bot::check_sending_message(msg.reply(&ctx, "Presence updated."));
+
+    Ok(())
+}
+
+#[command("reload-config")]
+#[owners_only]
+#[description("Reloads the config file and env vars. The token, shards, log level, http server and storage paths only change on restart.")]
+fn admin_reload_config(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let config = Config::load().map_err(|errors| {
+        format!(
+            "The config wasn't reloaded:\n{}",
+            errors
+                .iter()
+                .map(|e| format!("- {}", e))
+                .collect::<Vec<String>>()
+                .join("\n")
+        )
+    })?;
+
+    context::state::<AutoVoice>(&ctx.data)?
+        .lock()
+        .configure(&config.voice);
+    context::state::<CountdownManager>(&ctx.data)?.lock().policy =
+        AnnouncementPolicy::from_config(&config.countdown);
+    context::state::<SpeechManager>(&ctx.data)?
+        .lock()
+        .configure(&config.speech);
+
+    config::set(config);
+    info!("Config reloaded by {}", msg.author.id);
+    bot::check_sending_message(msg.reply(ctx, "Config reloaded."));
+
+    Ok(())
+}
+
+#[command("announce")]
+#[owners_only]
+#[min_args(1)]
+#[description("Posts a message to the announcement channel of every guild that set one.")]
+#[example("announce The bot restarts at 04:00 UTC for maintenance.")]
+fn admin_announce(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let text = args.rest().trim();
+    let guild_ids: Vec<GuildId> = ctx.cache.read().guilds.keys().copied().collect();
+    let settings_lock = context::state::<SettingsStore>(&ctx.data)?;
+    let channels: Vec<ChannelId> = {
+        let settings = settings_lock.lock();
+        guild_ids
+            .into_iter()
+            .filter_map(|guild_id| settings.get(guild_id).announce_channel)
+            .collect()
+    };
+
+    let sent = channels
+        .iter()
+        .filter(|channel_id| match channel_id.say(&ctx.http, text) {
+            Ok(_) => true,
+            Err(why) => {
+                warn!("Unable to announce in {}: {:?}", channel_id, why);
+                false
+            }
+        })
+        .count();
+
+    bot::check_sending_message(msg.reply(
+        &ctx,
+        format!("Announced in {} of {} channels.", sent, channels.len()),
+    ));
+
+    Ok(())
+}
+
+#[command("shutdown")]
+#[owners_only]
+#[description("Shuts the bot down gracefully, like a SIGTERM.")]
+fn admin_shutdown(ctx: &mut Context, msg: &Message) -> CommandResult {
+    if shutdown::is_requested() {
+        return Err("Already shutting down.".into());
+    }
+
+    info!("Shutdown requested by {}", msg.author.id);
+    bot::check_sending_message(msg.reply(&ctx, "Shutting down, bye!"));
+
+    // from another thread, as shutting down waits on the shards that run commands.
+    let data = Arc::clone(&ctx.data);
+    let http = Arc::clone(&ctx.http);
+    std::thread::spawn(move || shutdown::request(&data, &http));
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use crate as bot;
+use bot::context;
+use bot::settings::{SettingsStore, KEYS};
+
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+#[command("get")]
+#[only_in(guilds)]
+#[required_permissions(ADMINISTRATOR)]
+#[max_args(1)]
+#[description("Shows a setting of this server, or all of them.")]
+#[example("get prefix")]
+pub fn config_get(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+    let settings = context::state::<SettingsStore>(&ctx.data)?
+        .lock()
+        .get(guild_id);
+
+    let message = match args.current() {
+        Some(key) => format!("`{}`: {}", key, settings.get(&key.to_lowercase())?),
+        None => KEYS
+            .iter()
+            .map(|key| Ok(format!("`{}`: {}", key, settings.get(key)?)))
+            .collect::<Result<Vec<String>, String>>()?
+            .join("\n"),
+    };
+
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, message));
+
+    Ok(())
+}
+
+#[command("set")]
+#[only_in(guilds)]
+#[required_permissions(ADMINISTRATOR)]
+#[min_args(2)]
+#[description("Changes a setting of this server.")]
+#[usage("<key> <value>")]
+#[example("set announce_channel #rallies")]
+pub fn config_set(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+    let key = args.single::<String>()?.to_lowercase();
+    let value = args.rest();
+
+    context::state::<SettingsStore>(&ctx.data)?
+        .lock()
+        .update(guild_id, |settings| settings.set(&key, value))?;
+
+    bot::check_sending_message(msg.reply(&ctx, format!("`{}` updated.", key)));
+
+    Ok(())
+}
+
+#[command("reset")]
+#[only_in(guilds)]
+#[required_permissions(ADMINISTRATOR)]
+#[num_args(1)]
+#[description("Sets a setting of this server back to its default.")]
+#[example("reset prefix")]
+pub fn config_reset(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+    let key = args.rest().trim().to_lowercase();
+
+    context::state::<SettingsStore>(&ctx.data)?
+        .lock()
+        .update(guild_id, |settings| settings.reset(&key))?;
+
+    bot::check_sending_message(msg.reply(&ctx, format!("`{}` is back to its default.", key)));
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use crate as bot;
+use bot::context::{self, BotError};
+use bot::speech::SpeechManager;
+use bot::VoiceManager;
+
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::sync::Arc;
+
+#[command]
+#[only_in(guilds)]
+#[description("Turns voice commands on or off. Say \"bot ready\", \"bot status\" or \"bot cancel\" to manage the Roll Call. Speech is recognized locally, nothing is stored.")]
+#[usage("<on|off>")]
+#[example("listen on")]
+pub fn listen(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    let speech_lock = context::state::<SpeechManager>(&ctx.data)?;
+    match args
+        .single::<String>()
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "on" => (),
+        "off" => {
+            let message = if speech_lock.lock().stop(guild_id) {
+                "No longer listening for voice commands"
+            } else {
+                "Wasn't listening"
+            };
+            bot::check_sending_message(msg.channel_id.say(&ctx.http, message));
+
+            return Ok(());
+        }
+        _ => {
+            let message = if speech_lock.lock().is_listening(guild_id) {
+                "Listening for voice commands, use `listen off` to stop."
+            } else {
+                "Not listening, use `listen on` to start."
+            };
+            bot::check_sending_message(msg.reply(&ctx, message));
+
+            return Ok(());
+        }
+    }
+
+    // make sure packets are received, deafened handlers get none
+    let receiver = bot::Receiver::new(guild_id, &ctx.data, Arc::clone(&ctx.http))?;
+
+    let voice_lock = context::state::<VoiceManager>(&ctx.data)?;
+    let mut manager = voice_lock.lock();
+    let handler = match manager.get_mut(guild_id) {
+        Some(handler) if handler.channel_id.is_some() => handler,
+        _ => return Err(BotError::NotInVoice.into()),
+    };
+
+    speech_lock.lock().start(guild_id, msg.channel_id)?;
+
+    handler.listen(Some(Box::new(receiver)));
+    handler.deafen(false);
+
+    let wake = speech_lock.lock().wake_word();
+    bot::check_sending_message(msg.channel_id.say(
+        &ctx.http,
+        format!(
+            "Listening for voice commands in {}: say \"{} ready\", \"{} status\" or \"{} cancel\". Speech is recognized on the bot's machine and not stored.",
+            handler.channel_id.unwrap().mention(),
+            wake,
+            wake,
+            wake
+        ),
+    ));
+
+    Ok(())
+}
@@ -1,6 +1,142 @@
+pub mod admin;
+pub mod config;
+pub mod listen;
 pub mod ping;
+pub mod queue;
+pub mod record;
+pub mod remind;
+pub mod roll_call;
 pub mod say;
-pub mod time;
 pub mod shard;
+pub mod soundboard;
+pub mod time;
 pub mod voice;
-pub mod roll_call;
\ No newline at end of file
+
+use serenity::framework::standard::{Command, CommandGroup};
+
+/// The name of a command behind the prefix of its group, like `record start`,
+/// which tells apart the commands of different groups sharing a name.
+fn qualified(prefix: Option<&str>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{} {}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+fn commands<'a>(
+    groups: &'a [&'static CommandGroup],
+) -> impl Iterator<Item = (&'static CommandGroup, &'static Command)> + 'a {
+    groups
+        .iter()
+        .flat_map(|group| group.commands.iter().map(move |command| (*group, *command)))
+}
+
+/// The qualified name of the command, or group prefix, `name` refers to,
+/// aliases resolved. `None` if there's no such command.
+pub fn resolve(groups: &[&'static CommandGroup], name: &str) -> Option<String> {
+    let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if let Some(group) = groups
+        .iter()
+        .find(|g| g.options.prefixes.contains(&name.as_str()))
+    {
+        return group.options.prefixes.first().map(|p| p.to_string());
+    }
+
+    commands(groups).find_map(|(group, command)| {
+        let prefixes: Vec<Option<&str>> = match group.options.prefixes {
+            [] => vec![None],
+            prefixes => prefixes.iter().cloned().map(Some).collect(),
+        };
+        let refers = prefixes.iter().any(|prefix| {
+            command
+                .options
+                .names
+                .iter()
+                .any(|alias| qualified(*prefix, alias) == name)
+        });
+
+        refers.then(|| {
+            qualified(
+                group.options.prefixes.first().cloned(),
+                command.options.names[0],
+            )
+        })
+    })
+}
+
+/// The qualified name of the command `content` ran, the framework only gives
+/// its own `name`. When groups share the name, the one whose prefix comes
+/// right before the name in `content` wins, once the bot's `prefix` is
+/// stripped from the first word.
+pub fn invoked(
+    groups: &[&'static CommandGroup],
+    prefix: &str,
+    content: &str,
+    name: &str,
+) -> String {
+    let prefix = prefix.to_lowercase();
+    let mut words: Vec<String> = content.split_whitespace().map(str::to_lowercase).collect();
+    if let Some(first) = words.first_mut() {
+        if let Some(stripped) = first.strip_prefix(prefix.as_str()) {
+            *first = stripped.to_string();
+        }
+    }
+    let candidates = commands(groups).filter(|(_, command)| command.options.names[0] == name);
+    let mut fallback = None;
+
+    for (group, command) in candidates {
+        match group.options.prefixes.first() {
+            None => fallback = Some(name.to_string()),
+            Some(group_prefix) => {
+                let follows_prefix = words.windows(2).any(|pair| {
+                    group.options.prefixes.iter().any(|p| pair[0] == *p)
+                        && command.options.names.contains(&pair[1].as_str())
+                });
+                if follows_prefix {
+                    return qualified(Some(group_prefix), name);
+                }
+                fallback = fallback.or_else(|| Some(qualified(Some(group_prefix), name)));
+            }
+        }
+    }
+
+    fallback.unwrap_or_else(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GROUPS;
+
+    #[test]
+    fn resolves_qualified_names() {
+        assert_eq!(resolve(GROUPS, "vsay").as_deref(), Some("vsay"));
+        assert_eq!(
+            resolve(GROUPS, "record  start").as_deref(),
+            Some("record start")
+        );
+        assert_eq!(resolve(GROUPS, "sb").as_deref(), Some("sb"));
+        assert_eq!(resolve(GROUPS, "start"), None);
+        assert_eq!(resolve(GROUPS, "sb vsay"), None);
+    }
+
+    #[test]
+    fn tells_apart_commands_sharing_a_name() {
+        assert_eq!(
+            invoked(GROUPS, ".", ".record start", "start"),
+            "record start"
+        );
+        assert_eq!(invoked(GROUPS, "!", "!RC start 10", "start"), "rc start");
+        assert_eq!(invoked(GROUPS, ".", ".record stop", "stop"), "record stop");
+        assert_eq!(invoked(GROUPS, ".", ". record stop", "stop"), "record stop");
+        assert_eq!(invoked(GROUPS, ".", ".stop", "stop"), "stop");
+        assert_eq!(invoked(GROUPS, ".", "<@1> sb play boom", "play"), "sb play");
+        assert_eq!(invoked(GROUPS, ".", ".help", "help"), "help");
+    }
+
+    #[test]
+    fn matches_group_prefixes_exactly() {
+        assert_eq!(invoked(GROUPS, "d", "dsb play boom", "play"), "sb play");
+        assert_eq!(invoked(GROUPS, ".", ".xrecord stop", "stop"), "stop");
+    }
+}
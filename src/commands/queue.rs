@@ -0,0 +1,160 @@
+use crate as bot;
+use bot::context::{self, BotError};
+use bot::playback::PlaybackManager;
+
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+#[command]
+#[only_in(guilds)]
+#[description("Shows what is playing and queued in voice.")]
+pub fn queue(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    let status = context::state::<PlaybackManager>(&ctx.data)?
+        .lock()
+        .status(guild_id);
+
+    let mut message_builder = serenity::utils::MessageBuilder::new();
+    message_builder.push_bold_line("Voice Queue");
+    match &status.now_playing {
+        Some((title, priority)) => message_builder
+            .push_italic("Now playing:")
+            .push_line_safe(format!(" {} ({})", title, priority.name())),
+        None => message_builder.push_italic_line("Nothing playing"),
+    };
+
+    if status.paused {
+        message_builder.push_line("Paused");
+    }
+
+    for (title, priority) in &status.suspended {
+        message_builder
+            .push_italic("Interrupted:")
+            .push_line_safe(format!(" {} ({})", title, priority.name()));
+    }
+
+    for (position, (title, priority)) in status.upcoming.iter().enumerate() {
+        message_builder.push_line_safe(format!(
+            "{}. {} ({})",
+            position + 1,
+            title,
+            priority.name()
+        ));
+    }
+
+    let message = message_builder
+        .push_italic("Volume:")
+        .push_line(format!(" {:.0}%", status.volume * 100.0))
+        .build();
+
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, message));
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Skips what is currently playing in voice.")]
+pub fn skip(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    let title = context::state::<PlaybackManager>(&ctx.data)?
+        .lock()
+        .skip(guild_id)
+        .ok_or("Nothing is playing")?;
+    bot::check_sending_message(
+        msg.channel_id
+            .say(&ctx.http, format!("Skipped \"{}\"", title)),
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Pauses voice playback.")]
+pub fn pause(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    if !context::state::<PlaybackManager>(&ctx.data)?
+        .lock()
+        .pause(guild_id)
+    {
+        return Err(BotError::from("Already paused").into());
+    }
+
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, "Paused"));
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Resumes paused voice playback.")]
+pub fn resume(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    if !context::state::<PlaybackManager>(&ctx.data)?
+        .lock()
+        .resume(guild_id)
+    {
+        return Err(BotError::from("Not paused").into());
+    }
+
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, "Resumed"));
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Stops voice playback and clears the queue.")]
+pub fn stop(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    let cleared = context::state::<PlaybackManager>(&ctx.data)?
+        .lock()
+        .stop(guild_id);
+    bot::check_sending_message(
+        msg.channel_id
+            .say(&ctx.http, format!("Stopped, {} track(s) cleared", cleared)),
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[max_args(1)]
+#[description("Shows or sets the voice playback volume, in percent up to 200.")]
+#[example("volume 50")]
+pub fn volume(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    let manager_lock = context::state::<PlaybackManager>(&ctx.data)?;
+    if args.is_empty() {
+        let volume = manager_lock.lock().status(guild_id).volume;
+        bot::check_sending_message(
+            msg.channel_id
+                .say(&ctx.http, format!("Volume is {:.0}%", volume * 100.0)),
+        );
+
+        return Ok(());
+    }
+
+    let percent = args
+        .single::<f32>()
+        .ok()
+        .filter(|percent| percent.is_finite())
+        .ok_or("Volume must be a number between 0 and 200.")?;
+
+    let volume = manager_lock.lock().set_volume(guild_id, percent / 100.0);
+    bot::check_sending_message(
+        msg.channel_id
+            .say(&ctx.http, format!("Volume set to {:.0}%", volume * 100.0)),
+    );
+
+    Ok(())
+}
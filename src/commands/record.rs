@@ -0,0 +1,96 @@
+use crate as bot;
+use bot::commands::roll_call;
+use bot::context::{self, BotError};
+use bot::playback::{PlaybackManager, Priority, Track};
+use bot::recording::{self, RecordingManager};
+use bot::settings;
+use bot::tts::TextToSpeech;
+use bot::VoiceManager;
+
+use serenity::framework::standard::{macros::command, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serenity::voice::pcm;
+use std::sync::Arc;
+
+#[command("start")]
+#[only_in(guilds)]
+#[description("Starts recording the voice channel the bot is in. Everyone in it is told they are being recorded. Only Roll Call managers can start one.")]
+pub fn record_start(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+    roll_call::check_manager(&ctx.cache, &ctx.data, guild_id, msg.author.id)?;
+
+    let voice_lock = context::state::<VoiceManager>(&ctx.data)?;
+
+    let channel_id = {
+        let mut manager = voice_lock.lock();
+        match manager.get_mut(guild_id) {
+            Some(handler) if handler.channel_id.is_some() => {
+                // make sure packets are received, deafened handlers get none
+                let receiver = bot::Receiver::new(guild_id, &ctx.data, Arc::clone(&ctx.http))?;
+                handler.listen(Some(Box::new(receiver)));
+                handler.deafen(false);
+
+                handler.channel_id.unwrap()
+            }
+            _ => return Err(BotError::NotInVoice.into()),
+        }
+    };
+
+    context::state::<RecordingManager>(&ctx.data)?
+        .lock()
+        .start(guild_id, channel_id, msg.author.id, msg.channel_id)?;
+
+    info!(
+        "[{}] Recording of {} started by {}",
+        guild_id, channel_id, msg.author.id
+    );
+
+    bot::check_sending_message(msg.channel_id.say(
+        &ctx.http,
+        format!(
+            "**Recording started** in {} by {}. Everyone in the channel is being recorded, leave it if you don't consent. Use `record stop` to stop.",
+            channel_id.mention(),
+            msg.author.mention()
+        ),
+    ));
+
+    // also tell the people in voice, who might not be reading the channel
+    let manager_lock = context::state::<PlaybackManager>(&ctx.data)?;
+    match settings::of(&ctx.data, guild_id)
+        .voicerss()
+        .get_speech("This channel is now being recorded.")
+    {
+        Ok(speech) => {
+            let track = Track::new(
+                "recording notice",
+                Priority::Announcement,
+                pcm(true, speech),
+            );
+            let result = manager_lock.lock().enqueue(guild_id, track);
+            if let Err(why) = result {
+                warn!("[{}] Unable to announce the recording: {}", guild_id, why);
+            }
+        }
+        Err(why) => warn!("[{}] Unable to announce the recording: {}", guild_id, why),
+    }
+
+    Ok(())
+}
+
+#[command("stop")]
+#[only_in(guilds)]
+#[description("Stops the recording and saves the per user and mixed down tracks. Only Roll Call managers can stop one.")]
+pub fn record_stop(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+    roll_call::check_manager(&ctx.cache, &ctx.data, guild_id, msg.author.id)?;
+
+    let recording = context::state::<RecordingManager>(&ctx.data)?
+        .lock()
+        .stop(guild_id)
+        .ok_or("Not recording")?;
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, "Saving the recording..."));
+    recording::spawn_finish(Arc::clone(&ctx.http), recording, "stopped");
+
+    Ok(())
+}
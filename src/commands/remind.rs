@@ -0,0 +1,172 @@
+use crate as bot;
+use bot::context::{self, BotError};
+use bot::duration::{self, MAX_REMINDER};
+use bot::reminders::{ReminderStore, Target};
+
+use chrono::Utc;
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serenity::utils::{content_safe, parse_channel, ContentSafeOptions};
+
+const USAGE: &str =
+    "Use `remind me in 2h check the raid`, `remind #channel at 20:00 rally starts` or `remind voice in 10m stretch`.";
+
+#[command]
+#[min_args(1)]
+#[description("Sets a reminder for you, a channel, or spoken in voice. Use `list` to see pending reminders and `delete <id>` to remove one.")]
+#[usage("<me|#channel|voice> <in 2h|at 20:00 [timezone]> <text>")]
+#[example("remind me in 2h check the raid")]
+pub fn remind(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+    let first = args.single::<String>().unwrap_or_default().to_lowercase();
+    let (target, channel_id) = match first.as_str() {
+        "list" => return list(ctx, msg),
+        "delete" | "remove" => return delete(ctx, msg, args),
+        "me" => (Target::Author, msg.channel_id),
+        "voice" => {
+            context::guild_id(msg)?;
+
+            (Target::Voice, msg.channel_id)
+        }
+        mention => match parse_channel(mention).map(ChannelId) {
+            Some(channel_id) if in_guild(ctx, channel_id, msg.guild_id) => {
+                if !can_send(ctx, context::guild_id(msg)?, channel_id, msg.author.id) {
+                    return Err(BotError::from("You can't send messages in that channel.").into());
+                }
+
+                (Target::Channel, channel_id)
+            }
+            Some(_) => {
+                return Err(BotError::from("I can only remind channels of this guild.").into())
+            }
+            None => return Err(BotError::from(USAGE).into()),
+        },
+    };
+
+    let now = duration::now();
+    let when = match args
+        .single::<String>()
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "in" => args.single::<String>().unwrap_or_default(),
+        "at" => {
+            let time = args.single::<String>().unwrap_or_default();
+            let with_zone = args.current().map(|zone| format!("at {} {}", time, zone));
+            match with_zone.filter(|w| duration::parse(w, now).is_ok()) {
+                Some(with_zone) => {
+                    args.advance();
+
+                    with_zone
+                }
+                None => format!("at {}", time),
+            }
+        }
+        _ => return Err(BotError::from(USAGE).into()),
+    };
+
+    let delay = duration::parse_within(&when, now, MAX_REMINDER)?;
+
+    let settings = match msg.guild_id {
+        Some(guild_id) => ContentSafeOptions::default()
+            .clean_channel(false)
+            .display_as_member_from(guild_id),
+        None => ContentSafeOptions::default().clean_channel(false),
+    };
+    let text = content_safe(&ctx.cache, args.rest(), &settings);
+
+    let due = Utc::now() + chrono::Duration::seconds(delay.as_secs() as i64);
+    let id = context::state::<ReminderStore>(&ctx.data)?.lock().add(
+        msg.author.id,
+        msg.guild_id,
+        channel_id,
+        target,
+        due,
+        &text,
+    )?;
+    bot::check_sending_message(msg.channel_id.say(
+        &ctx.http,
+        format!("Reminder `#{}` set, in {}", id, duration::format(delay)),
+    ));
+
+    Ok(())
+}
+
+fn in_guild(ctx: &Context, channel_id: ChannelId, guild_id: Option<GuildId>) -> bool {
+    match ctx.cache.read().guild_channel(channel_id) {
+        Some(channel) => Some(channel.read().guild_id) == guild_id,
+        None => false,
+    }
+}
+
+/// Whether a user could post in a channel themselves, so reminders can't be
+/// used to post where they can't.
+fn can_send(ctx: &Context, guild_id: GuildId, channel_id: ChannelId, user_id: UserId) -> bool {
+    match ctx.cache.read().guild(guild_id) {
+        Some(guild) => guild
+            .read()
+            .user_permissions_in(channel_id, user_id)
+            .contains(Permissions::SEND_MESSAGES),
+        None => false,
+    }
+}
+
+fn list(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let store_lock = context::state::<ReminderStore>(&ctx.data)?;
+    let store = store_lock.lock();
+    let reminders = store.list(msg.guild_id, msg.author.id);
+    if reminders.is_empty() {
+        bot::check_sending_message(msg.channel_id.say(&ctx.http, "No pending reminders"));
+
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let mut message_builder = serenity::utils::MessageBuilder::new();
+    message_builder.push_bold_line("Reminders");
+    for reminder in reminders {
+        let left = (reminder.due - now).to_std().unwrap_or_default();
+        let target = match reminder.target {
+            Target::Author => String::from("here"),
+            Target::Channel => reminder.channel_id.mention(),
+            Target::Voice => String::from("in voice"),
+        };
+
+        message_builder
+            .push_mono(format!("#{}", reminder.id))
+            .push(format!(" in {}, {} ", duration::format(left), target))
+            .push_italic_safe(format!("by {}", author_name(ctx, reminder.author)))
+            .push(": ")
+            .push_line_safe(&reminder.text);
+    }
+
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, message_builder.build()));
+
+    Ok(())
+}
+
+fn author_name(ctx: &Context, user_id: UserId) -> String {
+    match ctx.cache.read().user(user_id) {
+        Some(user) => user.read().name.clone(),
+        None => user_id.to_string(),
+    }
+}
+
+fn delete(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+    let id = args
+        .single::<String>()
+        .ok()
+        .and_then(|id| id.trim_start_matches('#').parse::<u64>().ok())
+        .ok_or("Use `remind delete <id>`, see `remind list`.")?;
+
+    context::state::<ReminderStore>(&ctx.data)?
+        .lock()
+        .remove(id, msg.guild_id, msg.author.id)?;
+    bot::check_sending_message(
+        msg.channel_id
+            .say(&ctx.http, format!("Reminder `#{}` deleted", id)),
+    );
+
+    Ok(())
+}
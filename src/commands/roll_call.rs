@@ -1,56 +1,104 @@
 use crate as bot;
+use bot::context::{self, BotError, BotResult};
+use bot::settings;
 use bot::RollCallManager;
 
 // use std::time::Instant;
 // use chrono::{NaiveTime, Timelike};
-use serenity::framework::standard::{macros::command, Args, CommandError, CommandResult};
+use serenity::cache::Cache;
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::http::Http;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
 #[command]
-#[num_args(1)]
-#[description("Start a Roll Call if one is not currently active.")]
+#[max_args(1)]
+#[description("Start a Roll Call if one is not currently active. The number of players can be left out when the server has a default one.")]
 #[example("start 10")]
 #[aliases(start)]
 #[only_in(guilds)]
 pub fn start(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
-    let requested_player_num = args.parse::<u16>()?;
-    let guild_id = match msg.guild_id {
-        Some(guild) => guild,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
-
-            return Ok(());
-        }
-    };
+    let guild_id = context::guild_id(msg)?;
+    check_manager(&ctx.cache, &ctx.data, guild_id, msg.author.id)?;
+
+    let settings = settings::of(&ctx.data, guild_id);
+    let requested_player_num = if args.is_empty() {
+        settings.roll_call_players
+    } else {
+        args.parse::<u16>().ok()
+    }
+    .filter(|players| *players > 0)
+    .ok_or("How many players? Use `rc start 10`.")?;
+
+    let manager_lock = context::state::<RollCallManager>(&ctx.data)?;
+    let mut manager = manager_lock.lock();
+    if manager.have_running_call_for(guild_id) {
+        return Err(BotError::from(
+            "A Roll-Call is currently running. You need to cancel that one first.",
+        )
+        .into());
+    }
 
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<RollCallManager>()
-        .cloned()
-        .expect("Expected RollCallManager in ShareMap.");
-    {
-        let mut manager = manager_lock.lock();
-        if manager.have_running_call_for(guild_id) {
-            bot::check_sending_message(msg.channel_id.say(
-                &ctx.http,
-                "A Roll-Call is currently running. You need to cancel that one first.",
-            ));
-
-            return Ok(());
-        }
-
-        if manager.start_roll_call_for(guild_id, msg.author.id, requested_player_num) {
-            let message = format!("@here, A Roll-Call was activated by <@{}>!\nIt is requested that {} players join it! Be the first.", msg.author.id, requested_player_num);
-            bot::check_sending_message(msg.channel_id.say(&ctx.http, message));
-        }
-
-        Ok(())
+    if manager.start_roll_call_for(guild_id, msg.author.id, requested_player_num) {
+        let message = format!("@here, A Roll-Call was activated by <@{}>!\nIt is requested that {} players join it! Be the first.", msg.author.id, requested_player_num);
+        let channel_id = settings.announce_channel.unwrap_or(msg.channel_id);
+        bot::check_sending_message(channel_id.say(&ctx.http, message));
     }
+
+    Ok(())
+}
+
+/// Only administrators and the guild's manager role can start and cancel
+/// Roll Calls or recordings, when it has one.
+pub fn check_manager(
+    cache: &RwLock<Cache>,
+    data: &RwLock<ShareMap>,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> BotResult {
+    if settings::of(data, guild_id).manager_role.is_none() {
+        return Ok(());
+    }
+
+    require_manager(cache, data, guild_id, user_id)
+}
+
+/// Like `check_manager`, but only administrators pass when the guild has no
+/// manager role.
+pub fn require_manager(
+    cache: &RwLock<Cache>,
+    data: &RwLock<ShareMap>,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> BotResult {
+    let role_id = settings::of(data, guild_id).manager_role;
+
+    let cache = cache.read();
+    let guild_lock = cache.guild(guild_id).ok_or(BotError::GuildOnly)?;
+    let guild = guild_lock.read();
+    let is_manager = guild.member_permissions(user_id).administrator()
+        || role_id.is_some_and(|role_id| {
+            guild
+                .members
+                .get(&user_id)
+                .is_some_and(|member| member.roles.contains(&role_id))
+        });
+    if is_manager {
+        return Ok(());
+    }
+
+    let managers = match role_id {
+        Some(role_id) => guild
+            .roles
+            .get(&role_id)
+            .map_or_else(|| String::from("managers"), |role| role.name.clone()),
+        None => String::from("administrators"),
+    };
+
+    Err(BotError::from(format!(
+        "Only {} can do that here.",
+        managers
+    )))
 }
 
 #[command]
@@ -58,57 +106,58 @@ pub fn start(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
 #[description("Sets you ready by joining you in the Roll Call")]
 #[aliases(ready)]
 pub fn ready(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild_id = match msg.guild_id {
-        Some(guild) => guild,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
-
-            return Ok(());
-        }
-    };
+    let guild_id = context::guild_id(msg)?;
+    join_roll_call(
+        &ctx.data,
+        &ctx.http,
+        guild_id,
+        msg.channel_id,
+        msg.author.id,
+    )?;
 
-    use crate::RollCallManager;
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<RollCallManager>()
-        .cloned()
-        .expect("Expected RollCallManager in ShareMap.");
-    {
-        let mut manager = manager_lock.lock();
-        if !manager.have_running_call_for(guild_id) {
-            bot::check_sending_message(
-                msg.reply(&ctx, "There's no currently active Roll Call to join."),
-            );
-
-            return Ok(());
-        }
-
-        let joined = manager.join_user_to_call(guild_id, msg.author.id);
-        if joined == true {
-            bot::check_sending_message(msg.reply(&ctx, "You're ready!!"));
-
-            let left = manager.get_roll_call_for(guild_id).unwrap().lack();
-            let message = if left == 0 {
-                format!("@here, Roll Call complete!!! BURNNNNN!!!!")
-            } else {
-                format!("@here, {} players left!", left)
-            };
-
-            if left == 0 {
-                manager.cancel_running_call_for(guild_id);
-            }
-
-            bot::check_sending_message(msg.channel_id.say(&ctx.http, message));
-        } else {
-            // if we got here is because user is already joined.
-            bot::check_sending_message(msg.reply(&ctx, "You already joined. relax!"));
-        }
+    Ok(())
+}
+
+/// Joins the user to the guild's Roll Call, answering in `channel_id`.
+pub fn join_roll_call(
+    data: &RwLock<ShareMap>,
+    http: &Http,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+) -> BotResult {
+    let manager_lock = context::state::<RollCallManager>(data)?;
+    let mut manager = manager_lock.lock();
+    if !manager.have_running_call_for(guild_id) {
+        return Err(BotError::NoRollCall);
     }
 
+    if !manager.join_user_to_call(guild_id, user_id) {
+        // if we got here is because user is already joined.
+        return Err(BotError::from("You already joined. relax!"));
+    }
+
+    bot::check_sending_message(
+        channel_id.say(http, format!("{}: You're ready!!", user_id.mention())),
+    );
+
+    let left = manager
+        .get_roll_call_for(guild_id)
+        .map(|rc| rc.lack())
+        .unwrap_or(0);
+    let message = if left == 0 {
+        manager.cancel_running_call_for(guild_id);
+
+        "@here, Roll Call complete!!! BURNNNNN!!!!".to_string()
+    } else {
+        format!("@here, {} players left!", left)
+    };
+
+    let announce_channel = settings::of(data, guild_id)
+        .announce_channel
+        .unwrap_or(channel_id);
+    bot::check_sending_message(announce_channel.say(http, message));
+
     Ok(())
 }
 
@@ -117,35 +166,34 @@ pub fn ready(ctx: &mut Context, msg: &Message) -> CommandResult {
 #[description("Cancels the active Roll Call")]
 #[aliases(cancel)]
 pub fn cancel(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild_id = match msg.guild_id {
-        Some(guild) => guild,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
-
-            return Ok(());
-        }
-    };
+    let guild_id = context::guild_id(msg)?;
+    check_manager(&ctx.cache, &ctx.data, guild_id, msg.author.id)?;
+    cancel_roll_call(&ctx.data, &ctx.http, guild_id, msg.channel_id)?;
+
+    Ok(())
+}
 
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<RollCallManager>()
-        .cloned()
-        .expect("Expected RollCallManager in ShareMap.");
+/// Cancels the guild's Roll Call, announcing it in `channel_id` or the
+/// guild's announcement channel.
+pub fn cancel_roll_call(
+    data: &RwLock<ShareMap>,
+    http: &Http,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> BotResult {
+    if !context::state::<RollCallManager>(data)?
+        .lock()
+        .cancel_running_call_for(guild_id)
     {
-        let mut manager = manager_lock.lock();
-        if manager.cancel_running_call_for(guild_id) {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "@here Roll-Call cancelled. :'("),
-            );
-        }
-
-        Ok(())
+        return Err(BotError::NoRollCall);
     }
+
+    let channel_id = settings::of(data, guild_id)
+        .announce_channel
+        .unwrap_or(channel_id);
+    bot::check_sending_message(channel_id.say(http, "@here Roll-Call cancelled. :'("));
+
+    Ok(())
 }
 
 #[command]
@@ -153,58 +201,46 @@ pub fn cancel(ctx: &mut Context, msg: &Message) -> CommandResult {
 #[description("Current Roll Call status")]
 #[aliases(status)]
 pub fn status(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild_id = match msg.guild_id {
-        Some(guild) => guild,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
-
-            return Ok(());
-        }
-    };
+    let guild_id = context::guild_id(msg)?;
+    roll_call_status(&ctx.data, &ctx.http, guild_id, msg.channel_id)?;
 
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<RollCallManager>()
-        .cloned()
-        .expect("Expected RollCallManager in ShareMap.");
-    {
-        let manager = manager_lock.lock();
-        if !manager.have_running_call_for(guild_id) {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "There's no active Roll-Call. Start one first"),
-            );
-
-            return Ok(());
-        }
-
-        let rc = manager.get_roll_call_for(guild_id).unwrap();
-        let mut message_builder = serenity::utils::MessageBuilder::new();
-        message_builder
-            .push_bold_line("Roll Call Status")
-            .push_italic("Started by:")
-            .push_line(format!(" {}", rc.call_by.mention()))
-            .push_italic("Players Requested:")
-            .push_line(format!(" {}", rc.requested))
-            .push_italic("Players Joined:")
-            .push_line(format!(" {}", rc.joined.len()));
-
-        for v in &rc.joined {
-            message_builder.push_line(format!("{} ", v.mention()));
-        }
-
-        let message = message_builder
-            .push_italic("Players missing:")
-            .push_line(format!(" {}", rc.lack()))
-            .push_line("@here")
-            .build();
-
-        bot::check_sending_message(msg.channel_id.say(&ctx.http, message));
+    Ok(())
+}
+
+/// Posts the status of the guild's Roll Call to `channel_id`.
+pub fn roll_call_status(
+    data: &RwLock<ShareMap>,
+    http: &Http,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> BotResult {
+    let manager_lock = context::state::<RollCallManager>(data)?;
+    let manager = manager_lock.lock();
+    let rc = manager
+        .get_roll_call_for(guild_id)
+        .ok_or(BotError::NoRollCall)?;
+
+    let mut message_builder = serenity::utils::MessageBuilder::new();
+    message_builder
+        .push_bold_line("Roll Call Status")
+        .push_italic("Started by:")
+        .push_line(format!(" {}", rc.call_by.mention()))
+        .push_italic("Players Requested:")
+        .push_line(format!(" {}", rc.requested))
+        .push_italic("Players Joined:")
+        .push_line(format!(" {}", rc.joined.len()));
+
+    for v in &rc.joined {
+        message_builder.push_line(format!("{} ", v.mention()));
     }
 
+    let message = message_builder
+        .push_italic("Players missing:")
+        .push_line(format!(" {}", rc.lack()))
+        .push_line("@here")
+        .build();
+
+    bot::check_sending_message(channel_id.say(http, message));
+
     Ok(())
 }
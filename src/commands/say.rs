@@ -26,7 +26,7 @@ fn say(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
             .clean_role(false)
     };
 
-    let content = serenity_util_content_safe(&ctx.cache, &args.rest(), &settings);
+    let content = serenity_util_content_safe(&ctx.cache, args.rest(), &settings);
     bot::check_sending_message(msg.channel_id.say(&ctx.http, &content));
 
     Ok(())
@@ -1,13 +1,17 @@
 use crate as bot;
+use bot::context;
 use bot::ShardManagerContainer;
 
 use serenity::prelude::*;
 use serenity::model::prelude::*;
 use serenity::framework::standard::{
+    Args,
     CommandResult,
     macros::command,
 };
 use serenity::client::bridge::gateway::ShardId;
+use serenity::utils::MessageBuilder;
+use std::collections::HashMap;
 
 #[command]
 fn latency(ctx: &mut Context, msg: &Message) -> CommandResult {
@@ -39,7 +43,76 @@ fn latency(ctx: &mut Context, msg: &Message) -> CommandResult {
         },
     };
 
-    bot::check_sending_message(msg.reply(&ctx, &format!("The shard latency is {:?}", runner.latency)));
+    bot::check_sending_message(msg.reply(&ctx, format!("The shard latency is {:?}", runner.latency)));
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[command]
+#[owners_only]
+#[description("Lists the shards this process runs, with their stage, latency and guilds.")]
+fn shards(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let shard_manager = context::state::<ShardManagerContainer>(&ctx.data)?;
+
+    let mut guilds: HashMap<u64, usize> = HashMap::new();
+    {
+        let cache = ctx.cache.read();
+        let total = cache.shard_count.max(1);
+        for guild_id in cache.guilds.keys() {
+            *guilds
+                .entry(serenity::utils::shard_id(guild_id.0, total))
+                .or_insert(0) += 1;
+        }
+    }
+
+    let manager = shard_manager.lock();
+    let runners = manager.runners.lock();
+    let mut ids: Vec<&ShardId> = runners.keys().collect();
+    ids.sort_by_key(|id| id.0);
+
+    let mut message = MessageBuilder::new();
+    message.push_bold_line(format!("{} shards", ids.len()));
+    for id in ids {
+        let runner = &runners[id];
+        let latency = runner
+            .latency
+            .map(|latency| format!("{}ms", latency.as_millis()))
+            .unwrap_or_else(|| String::from("unknown"));
+        message.push_line(format!(
+            "#{}: {}, latency {}, {} guilds",
+            id.0,
+            runner.stage,
+            latency,
+            guilds.get(&id.0).copied().unwrap_or(0)
+        ));
+    }
+
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, message.build()));
+
+    Ok(())
+}
+
+#[command("restart")]
+#[owners_only]
+#[num_args(1)]
+#[description("Restarts a shard, reconnecting it to the gateway.")]
+#[example("restart 0")]
+fn shard_restart(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let id = args
+        .parse::<u64>()
+        .map_err(|_| "Which shard? Use `shard restart 0`.")?;
+    let shard_manager = context::state::<ShardManagerContainer>(&ctx.data)?;
+
+    let mut manager = shard_manager.lock();
+    if !manager.has(ShardId(id)) {
+        return Err(format!("No shard #{} runs here, see `shards`.", id).into());
+    }
+
+    info!("Restarting shard #{} for {}", id, msg.author.id);
+    manager.restart(ShardId(id));
+    drop(manager);
+
+    bot::check_sending_message(msg.reply(&ctx, format!("Restarting shard #{}.", id)));
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use crate as bot;
+use bot::audio;
+use bot::commands::roll_call;
+use bot::context::{self, BotError};
+use bot::playback::{PlaybackManager, Priority, Track};
+use bot::soundboard::{Soundboard, MAX_CLIP_BYTES};
+
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::path::Path;
+
+#[command("list")]
+#[description(
+    "Lists the clips available in the soundboard, the ones added to this server included."
+)]
+pub fn sb_list(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let soundboard_lock = context::state::<Soundboard>(&ctx.data)?;
+    let names: Vec<String> = soundboard_lock
+        .lock()
+        .names(msg.guild_id)
+        .into_iter()
+        .map(|name| format!("`{}`", name))
+        .collect();
+
+    if names.is_empty() {
+        bot::check_sending_message(msg.channel_id.say(
+            &ctx.http,
+            "The soundboard is empty. Upload one with `sb add`.",
+        ));
+    } else {
+        bot::check_sending_message(
+            msg.channel_id
+                .say(&ctx.http, format!("Available clips: {}", names.join(", "))),
+        );
+    }
+
+    Ok(())
+}
+
+#[command("play")]
+#[only_in(guilds)]
+#[num_args(1)]
+#[description("Plays a soundboard clip in the voice channel the bot is in.")]
+#[example("play boom")]
+pub fn sb_play(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    let name = args.rest().trim();
+    let path = context::state::<Soundboard>(&ctx.data)?
+        .lock()
+        .get(guild_id, name)
+        .map(Path::to_path_buf)
+        .ok_or_else(|| format!("No clip named `{}`.", name))?;
+
+    let source = audio::file_source(&path).map_err(|e| {
+        error!("Unable to decode clip {:?}: {}", path, e);
+
+        "Unable to play that clip."
+    })?;
+
+    let track = Track::new(name.to_lowercase(), Priority::Chatter, source);
+    context::state::<PlaybackManager>(&ctx.data)?
+        .lock()
+        .enqueue(guild_id, track)?;
+
+    Ok(())
+}
+
+#[command("add")]
+#[only_in(guilds)]
+#[max_args(1)]
+#[description("Adds the attached wav, ogg or mp3 file to this server's soundboard. The clip is named after the file unless a name is given. Only administrators and Roll Call managers can add clips.")]
+#[example("add boom")]
+pub fn sb_add(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+    roll_call::require_manager(&ctx.cache, &ctx.data, guild_id, msg.author.id)?;
+
+    let attachment = msg
+        .attachments
+        .first()
+        .ok_or("Attach the audio file to the message.")?;
+    if attachment.size > MAX_CLIP_BYTES {
+        return Err(BotError::from("The clip is too big, max is 8MB.").into());
+    }
+
+    let name = match args.current() {
+        Some(name) => name.to_string(),
+        None => Path::new(&attachment.filename)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    let data = attachment.download().map_err(|why| {
+        error!("Unable to download attachment: {:?}", why);
+
+        "Unable to download the attachment."
+    })?;
+
+    context::state::<Soundboard>(&ctx.data)?.lock().add(
+        guild_id,
+        &name,
+        &attachment.filename,
+        &data,
+    )?;
+    bot::check_sending_message(
+        msg.channel_id
+            .say(&ctx.http, format!("Added clip `{}`.", name.to_lowercase())),
+    );
+
+    Ok(())
+}
@@ -1,58 +1,155 @@
-use chrono::{NaiveTime, Timelike};
-use serenity::prelude::*;
+use crate as bot;
+use bot::config;
+use bot::context::{self, BotError};
+use bot::countdown::{text_edit_interval, Countdown, CountdownManager};
+use bot::duration;
+
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::http::Http;
 use serenity::model::prelude::*;
-use serenity::framework::standard::{
-    CommandResult,
-    CommandError,
-    macros::command,
-};
+use serenity::prelude::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[command]
-fn time(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let mut iter = msg.content.split_whitespace();
-    let _z = iter.next(); // first values is the command "!time"
-    if let Some(v) = iter.next() {
-        match v.parse::<i32>() {
-            Ok(mut int_value) => {
-
-                // safeguard zero or below
-                if int_value < 0 {
-                    crate::check_sending_message(msg.channel_id.say(&ctx.http, "Supplied argument for seconds is two low!"));
-
-                    return Err(CommandError(String::from("Supplied argument for seconds is two low!")));
-                }
-
-                // safeguard max 3600
-                if int_value >3600 {
-                    crate::check_sending_message(msg.channel_id.say(&ctx.http, "Supplied argument for seconds is above max of 3600!"));
-                    
-                    return Err(CommandError(String::from("Supplied argument for seconds is above max of 3600!")));
-                }
-
-                while int_value >= 0 {
-                    let t = NaiveTime::from_num_seconds_from_midnight(int_value as u32, 0);
-                    let send_text = format!("COUNTDOWN: {} sec(s) - ({}h{}m{}s)", int_value, t.hour(), t.minute(), t.second());
-                    crate::check_sending_message(msg.channel_id.say(&ctx.http, send_text));
-
-                    if int_value == 0 {
-                        let boom = vec!["D:/Code/rust/m-bot/resources/gif/boom.gif"];
-                        crate::check_sending_message(msg.channel_id.send_files(&ctx.http, boom, |m| { m.content("") }));
-
-                        return Ok(());
-                    }
-
-                    int_value = int_value - 1;
-                    std::thread::sleep(std::time::Duration::from_millis(1000));
-                }
-            }
-            ,
-            Err(_) => {
-                crate::check_sending_message(msg.channel_id.say(&ctx.http, "Supplied argument for seconds must be present and an integer number above zero."));
+#[min_args(1)]
+#[description("Counts down in a message edited in place, for `90` seconds, `1m30s`, `1:30` or until `at 21:00 Europe/Lisbon`. Use `stop` to cancel it.")]
+#[example("time 1m30s")]
+fn time(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let countdowns_lock = context::state::<CountdownManager>(&ctx.data)?;
 
-                return Err(CommandError(String::from("Supplied argument for seconds must be present and an integer number above zero.")));
+    match args.current() {
+        Some("stop") => {
+            if !countdowns_lock.lock().cancel_text(msg.channel_id) {
+                return Err(BotError::from("No countdown is running").into());
             }
+
+            bot::check_sending_message(msg.channel_id.say(&ctx.http, "Countdown stopped"));
+
+            return Ok(());
         }
+        Some(_) => (),
+        None => return Ok(()),
     }
 
+    let max = config::current().limits.max_countdown();
+    let seconds = duration::parse_within(args.rest(), duration::now(), max)?.as_secs() as u32;
+
+    let message = match msg.channel_id.say(&ctx.http, countdown_text(seconds)) {
+        Ok(message) => message,
+        Err(why) => {
+            error!("Error sending message: {:?}", why);
+
+            return Ok(());
+        }
+    };
+
+    let duration = Duration::from_secs(u64::from(seconds));
+    let countdown = match countdowns_lock.lock().start_text(
+        msg.author.id,
+        msg.channel_id,
+        Instant::now(),
+        duration,
+    ) {
+        Some(countdown) => countdown,
+        None => {
+            let _ = message.delete(&ctx);
+
+            return Err(BotError::from(
+                "A countdown is already running here. Use `time stop` to cancel it.",
+            )
+            .into());
+        }
+    };
+
+    let http = Arc::clone(&ctx.http);
+    std::thread::spawn(move || {
+        run_text_countdown(&http, message.id, &countdown, seconds);
+        countdowns_lock.lock().finish_text(&countdown);
+    });
+
     Ok(())
-}
\ No newline at end of file
+}
+
+fn countdown_text(seconds: u32) -> String {
+    let (days, hours) = (seconds / 86400, seconds / 3600 % 24);
+    let (minutes, secs) = (seconds / 60 % 60, seconds % 60);
+
+    if days > 0 {
+        format!(
+            "COUNTDOWN: {} sec(s) - ({}d{}h{}m{}s)",
+            seconds, days, hours, minutes, secs
+        )
+    } else {
+        format!(
+            "COUNTDOWN: {} sec(s) - ({}h{}m{}s)",
+            seconds, hours, minutes, secs
+        )
+    }
+}
+
+/// The `boom.gif` inside the closest `resources` folder.
+fn boom_gif() -> Option<PathBuf> {
+    find_folder::Search::KidsThenParents(3, 5)
+        .for_folder("resources")
+        .map(|resources| resources.join("gif").join("boom.gif"))
+        .ok()
+        .filter(|path| path.is_file())
+}
+
+/// Edits the countdown message at the `text_edit_interval` cadence, each
+/// edit scheduled relative to the countdown start.
+fn run_text_countdown(http: &Http, message_id: MessageId, countdown: &Countdown, seconds: u32) {
+    let channel_id = countdown.channel_id;
+    for tick in 1..=seconds {
+        let remaining = seconds - tick;
+        if !remaining.is_multiple_of(text_edit_interval(remaining)) {
+            continue;
+        }
+
+        if !countdown.sleep_until(countdown.tick_at(tick)) {
+            bot::check_sending_message(channel_id.edit_message(http, message_id, |m| {
+                m.content(format!(
+                    "COUNTDOWN: stopped with {} sec(s) left",
+                    countdown.remaining().as_secs()
+                ))
+            }));
+
+            return;
+        }
+
+        let text = countdown_text(remaining);
+        if let Err(why) = channel_id.edit_message(http, message_id, |m| m.content(text)) {
+            warn!("Unable to edit countdown message: {:?}", why);
+        }
+    }
+
+    match boom_gif() {
+        Some(boom) => {
+            bot::check_sending_message(channel_id.send_files(http, vec![&boom], |m| m.content("")))
+        }
+        None => bot::check_sending_message(channel_id.say(http, "BOOM!")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_countdowns_up_to_the_max() {
+        assert_eq!(countdown_text(0), "COUNTDOWN: 0 sec(s) - (0h0m0s)");
+        assert_eq!(countdown_text(3661), "COUNTDOWN: 3661 sec(s) - (1h1m1s)");
+
+        let max = config::MAX_COUNTDOWN_LIMIT as u32;
+        assert_eq!(
+            countdown_text(max),
+            format!("COUNTDOWN: {} sec(s) - (1d0h0m0s)", max)
+        );
+        assert_eq!(
+            countdown_text(max - 1),
+            format!("COUNTDOWN: {} sec(s) - (23h59m59s)", max - 1)
+        );
+    }
+}
@@ -1,46 +1,41 @@
 extern crate reqwest;
 
 use crate as bot;
-use bot::tts::{AzureTextToSpeech, TextToSpeech, VoiceRSS};
+use bot::activity::VoiceActivity;
+use bot::audio;
+use bot::autovoice::AutoVoice;
+use bot::config;
+use bot::context::{self, BotError, BotResult};
+use bot::countdown::{AnnouncementPolicy, Countdown, CountdownManager, Finale};
+use bot::duration;
+use bot::matching::{self, Match};
+use bot::playback::{PlaybackManager, Priority, Track};
+use bot::recording::{self, RecordingManager};
+use bot::settings;
+use bot::soundboard::Soundboard;
+use bot::speech::SpeechManager;
+use bot::tts::TextToSpeech;
 use bot::VoiceManager;
 
-use chrono::{NaiveTime, Timelike};
-use serenity::framework::standard::{macros::command, Args, CommandError, CommandResult};
+use chrono::Utc;
+use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::http::Http;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
-use std::time::Instant;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
 use serenity::utils::content_safe as serenity_util_content_safe;
-use serenity::utils::ContentSafeOptions;
+use serenity::utils::{parse_channel, ContentSafeOptions};
 
 use serenity::voice::pcm;
 
 #[command]
+#[only_in(guilds)]
 fn vsay(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
-    let guild_id = match ctx.cache.read().guild_channel(msg.channel_id) {
-        Some(channel) => channel.read().guild_id,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
-
-            return Ok(());
-        }
-    };
-
-    info!("ARGS: {:?}", args);
-    let manager_lock = ctx.data.read().get::<VoiceManager>().cloned().unwrap();
-    let mut manager = manager_lock.lock();
-
-    let handler = match manager.get_mut(guild_id) {
-        Some(handler) => handler,
-        None => {
-            bot::check_sending_message(msg.reply(&ctx, "Not in a voice channel"));
-
-            return Ok(());
-        }
-    };
+    let guild_id = context::guild_id(msg)?;
 
     // GETING AUDIO FROM VOICERSS.ORG API
     let settings = if let Some(guild_id) = msg.guild_id {
@@ -54,244 +49,522 @@ fn vsay(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
             .clean_role(false)
     };
 
-    let voicerss_token = std::env::var("VOICERSS_TOKEN").expect("VOICE RSS TOKEN");
-    let content = serenity_util_content_safe(&ctx.cache, &args.rest(), &settings);
-    info!("CONTENT: {}", content);
-    let url = format!(
-        "http://api.voicerss.org/?key={}&c=wav&f=48Khz_16bit_stereo&r=4&hl=en-us&b64=false&src={}",
-        voicerss_token, content
-    );
-    info!("URL: {}", url);
-    let r = match reqwest::blocking::get(url.as_str()) {
-        Ok(r) => r,
-        Err(_) => {
-            bot::check_sending_message(msg.reply(&ctx, "Unable to create the vocalization."));
+    let content = serenity_util_content_safe(&ctx.cache, args.rest(), &settings);
+    if settings::of(&ctx.data, guild_id).log_messages {
+        info!("Saying: {}", content);
+    }
+    say(&ctx.data, guild_id, content)?;
 
-            return Ok(());
-        }
-    };
+    Ok(())
+}
+
+/// Speaks `text` in the voice channel the bot is in, after what's queued.
+pub fn say(data: &RwLock<ShareMap>, guild_id: GuildId, text: String) -> BotResult {
+    let manager_lock = context::state::<PlaybackManager>(data)?;
+    let r = settings::of(data, guild_id)
+        .voicerss()
+        .get_speech(&text)
+        .map_err(|_| "Unable to create the vocalization.")?;
 
-    handler.play(pcm(true, r));
+    let track = Track::new(text, Priority::Chatter, pcm(true, r));
+    manager_lock.lock().enqueue(guild_id, track)?;
 
     Ok(())
 }
 
 #[command]
+#[min_args(1)]
+#[description("Speaks a countdown in the voice channel, for `90` seconds, `1m30s`, `1:30` or until `at 21:00 Europe/Lisbon`. Use `stop` to cancel it or `status` to see how long is left.")]
+#[example("vtime 1m30s")]
+#[only_in(guilds)]
 fn vtime(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
-    let guild_id = match ctx.cache.read().guild_channel(msg.channel_id) {
-        Some(channel) => channel.read().guild_id,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
+    let guild_id = context::guild_id(msg)?;
+
+    debug!("vtime {}", args.rest());
+    let countdowns_lock = context::state::<CountdownManager>(&ctx.data)?;
+
+    match args.current() {
+        Some("stop") => {
+            if !countdowns_lock.lock().cancel_voice(guild_id) {
+                return Err(BotError::from("No countdown is running").into());
+            }
+
+            bot::check_sending_message(msg.channel_id.say(&ctx.http, "Countdown stopped"));
 
             return Ok(());
         }
+        Some("status") => {
+            let countdowns = countdowns_lock.lock();
+            let countdown = countdowns
+                .voice(guild_id)
+                .ok_or("No countdown is running")?;
+            bot::check_sending_message(msg.channel_id.say(
+                &ctx.http,
+                format!(
+                    "Countdown started by {} has {} sec(s) left",
+                    countdown.started_by.mention(),
+                    countdown.remaining().as_secs()
+                ),
+            ));
+
+            return Ok(());
+        }
+        Some(_) => (),
+        None => return Ok(()),
+    }
+
+    let max = config::current().limits.max_countdown();
+    let seconds = duration::parse_within(args.rest(), duration::now(), max)?.as_secs() as u32;
+
+    let in_voice = context::state::<VoiceManager>(&ctx.data)?
+        .lock()
+        .get(guild_id)
+        .is_some();
+    if !in_voice {
+        return Err(BotError::NotInVoice.into());
+    }
+
+    let duration = Duration::from_secs(u64::from(seconds));
+    let starts_at = Instant::now() + FIRST_TICK_DELAY;
+    let mut countdowns = countdowns_lock.lock();
+    let policy = countdowns.policy.clone();
+    let countdown = countdowns
+        .start_voice(guild_id, msg.author.id, msg.channel_id, starts_at, duration)
+        .ok_or("A countdown is already running. Use `vtime stop` to cancel it.")?;
+
+    drop(countdowns);
+
+    let data = Arc::clone(&ctx.data);
+    let http = Arc::clone(&ctx.http);
+    std::thread::spawn(move || {
+        run_voice_countdown(&data, &http, guild_id, &countdown, &policy, seconds);
+        countdowns_lock.lock().finish_voice(guild_id, &countdown);
+    });
+
+    Ok(())
+}
+
+/// Time before the first tick, giving the speech of the first ticks time to be fetched.
+const FIRST_TICK_DELAY: Duration = Duration::from_secs(1);
+
+/// How many ticks of speech are fetched ahead of the one due.
+const PREFETCH_TICKS: usize = 3;
+
+/// A tick whose speech is ready later than this after its due time is skipped
+/// instead of talking over the next one.
+const MAX_TICK_LATENESS: Duration = Duration::from_millis(500);
+
+/// Fetches the audio announcing `remaining` seconds, or of the finale.
+fn announcement(
+    service: &mut dyn TextToSpeech,
+    policy: &AnnouncementPolicy,
+    finale_clip: &Option<PathBuf>,
+    remaining: u32,
+) -> (String, Result<Vec<u8>, &'static str>) {
+    let text = match (policy.phrase(remaining), &policy.finale, finale_clip) {
+        (Some(text), _, _) => text,
+        (None, Finale::Sound(name), Some(path)) => {
+            let clip = audio::file_to_pcm(path)
+                .map(|samples| audio::pcm_to_bytes(&samples))
+                .map_err(|_| "Unable to decode the finale clip");
+
+            return (name.clone(), clip);
+        }
+        (None, Finale::Phrase(phrase), _) => phrase.clone(),
+        (None, Finale::Sound(_), None) => String::from("go!"),
     };
 
-    info!("ARGS: {:?}", args);
-    if let Some(seconds) = args.current() {
-        match seconds.parse::<i32>() {
-            Err(_) => {
-                crate::check_sending_message(msg.channel_id.say(&ctx.http, "Supplied argument for seconds must be present and an integer number above zero."));
+    let speech = service.get_speech(&text).and_then(|mut r| {
+        let mut buffer = Vec::new();
+        r.read_to_end(&mut buffer)
+            .map_err(|_| "Unable to read the vocalization")?;
+
+        Ok(buffer)
+    });
 
-                return Err(CommandError(String::from("Supplied argument for seconds must be present and an integer number above zero.")));
+    (text, speech)
+}
+
+/// Speaks the seconds of `countdown` the policy announces, each tick enqueued
+/// exactly on its second since the countdown start.
+///
+/// Speech is fetched by a separate thread a few ticks ahead, so TTS latency
+/// only matters if it's longer than `PREFETCH_TICKS` seconds.
+fn run_voice_countdown(
+    data: &Arc<RwLock<ShareMap>>,
+    http: &Http,
+    guild_id: GuildId,
+    countdown: &Countdown,
+    policy: &AnnouncementPolicy,
+    seconds: u32,
+) {
+    let finale_clip = match &policy.finale {
+        Finale::Sound(name) => {
+            let clip = context::state::<Soundboard>(data)
+                .ok()
+                .and_then(|soundboard| {
+                    soundboard.lock().get(guild_id, name).map(Path::to_path_buf)
+                });
+            if clip.is_none() {
+                warn!(
+                    "Countdown finale clip '{}' not found in the soundboard",
+                    name
+                );
             }
-            Ok(mut int_value) => {
-                // safeguard zero or below
-                if int_value < 0 {
-                    crate::check_sending_message(
-                        msg.channel_id
-                            .say(&ctx.http, "Supplied argument for seconds is two low!"),
-                    );
-
-                    return Err(CommandError(String::from(
-                        "Supplied argument for seconds is two low!",
-                    )));
-                }
-
-                // safeguard max 3600
-                if int_value > 3600 {
-                    crate::check_sending_message(msg.channel_id.say(
-                        &ctx.http,
-                        "Supplied argument for seconds is above max of 3600!",
-                    ));
-
-                    return Err(CommandError(String::from(
-                        "Supplied argument for seconds is above max of 3600!",
-                    )));
-                }
-
-                let manager_lock = ctx.data.read().get::<VoiceManager>().cloned().unwrap();
-                let mut manager = manager_lock.lock();
-
-                let handler = match manager.get_mut(guild_id) {
-                    Some(handler) => handler,
-                    None => {
-                        bot::check_sending_message(msg.reply(&ctx, "Not in a voice channel"));
-
-                        return Ok(());
-                    }
-                };
-
-                // let mut service = VoiceRSS::default();
-                let mut service = AzureTextToSpeech::default();
-                while int_value >= 0 {
-                    let now = Instant::now();
-                    let t = NaiveTime::from_num_seconds_from_midnight(int_value as u32, 0);
-
-                    let the_text;
-                    if t.minute() > 0 {
-                        the_text = format!("{}m{}s", t.minute(), t.second());
-                    } else {
-                        the_text = format!("{}", t.second());
-                    }
-
-                    let r = match service.get_speech(the_text.as_str()) {
-                        Ok(r) => r,
-                        Err(_) => {
-                            bot::check_sending_message(
-                                msg.reply(&ctx, "Unable to create the vocalization."),
-                            );
-
-                            return Ok(());
-                        }
-                    };
-
-                    while now.elapsed().as_millis() < 950 {
-                        std::thread::sleep(std::time::Duration::from_millis(25));
-                    }
-
-                    int_value = int_value - 1;
-                    //let _safe_audio: LockedAudio = handler.play_only(pcm(true, r));
-                    handler.play(pcm(false, r));
-                }
+
+            clip
+        }
+        Finale::Phrase(_) => None,
+    };
+
+    let manager_lock = match context::state::<PlaybackManager>(data) {
+        Ok(manager_lock) => manager_lock,
+        Err(why) => {
+            bot::check_sending_message(countdown.channel_id.say(http, why.to_string()));
+
+            return;
+        }
+    };
+
+    let settings = settings::of(data, guild_id);
+    let (sender, speeches) = mpsc::sync_channel(PREFETCH_TICKS);
+    let prefetch = countdown.clone();
+    let policy = policy.clone();
+    std::thread::spawn(move || {
+        let mut service = settings.azure();
+        for tick in 0..=seconds {
+            let remaining = seconds - tick;
+            if !policy.announces(remaining, seconds) {
+                continue;
+            }
+
+            if prefetch.is_cancelled() {
+                return;
+            }
+
+            let (text, speech) = announcement(&mut service, &policy, &finale_clip, remaining);
+            if sender.send((tick, text, speech)).is_err() {
+                return;
             }
         }
-    }
+    });
 
-    Ok(())
-}
+    let mut worst = Duration::from_secs(0);
+    let mut skipped = 0;
+    for (tick, text, speech) in speeches {
+        let speech = match speech {
+            Ok(speech) => speech,
+            Err(_) => {
+                bot::check_sending_message(
+                    countdown
+                        .channel_id
+                        .say(http, "Unable to create the vocalization."),
+                );
 
-#[command]
-#[description("Join a voice channel that you are also connected to.")]
-#[num_args(0)]
-fn join(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild = match msg.guild(&ctx.cache) {
-        Some(guild) => guild,
-        None => {
+                return;
+            }
+        };
+
+        let due = countdown.tick_at(tick);
+        if !countdown.sleep_until(due) {
+            return;
+        }
+
+        let drift = Instant::now().saturating_duration_since(due);
+        worst = worst.max(drift);
+        if drift > MAX_TICK_LATENESS {
+            skipped += 1;
+            debug!(
+                "[{}] Countdown tick '{}' skipped, {:?} late",
+                guild_id, text, drift
+            );
+
+            continue;
+        }
+
+        debug!("[{}] Countdown tick '{}' drift {:?}", guild_id, text, drift);
+        let track = Track::new(text, Priority::Countdown, pcm(true, Cursor::new(speech)));
+        if let Err(why) = manager_lock.lock().enqueue(guild_id, track) {
             bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
+                countdown
+                    .channel_id
+                    .say(http, format!("Countdown stopped: {}", why)),
             );
 
-            return Ok(());
+            return;
         }
-    };
+    }
 
-    let guild_id = guild.read().id;
+    debug!(
+        "[{}] Countdown of {}s done, worst drift {:?}, {} tick(s) skipped, ended {:?} after schedule",
+        guild_id,
+        seconds,
+        worst,
+        skipped,
+        Instant::now().saturating_duration_since(countdown.tick_at(seconds))
+    );
+}
+
+/// Why the bot can't join a voice channel.
+#[derive(Debug)]
+pub enum JoinError {
+    NotConnected,
+    NotFound(String),
+    Ambiguous(Vec<String>),
+    NotVoice,
+    AuthorCantConnect,
+    CantConnect,
+    CantSpeak,
+    Full,
+}
+
+impl std::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JoinError::NotConnected => write!(f, "Not in a voice channel"),
+            JoinError::NotFound(name) => write!(f, "No voice channel named \"{}\"", name),
+            JoinError::Ambiguous(names) => write!(
+                f,
+                "Which one? {}",
+                names
+                    .iter()
+                    .map(|n| format!("\"{}\"", n))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            JoinError::NotVoice => write!(f, "That's not a voice channel"),
+            JoinError::AuthorCantConnect => write!(f, "You can't connect to that channel"),
+            JoinError::CantConnect => write!(f, "I'm not allowed to connect to that channel"),
+            JoinError::CantSpeak => write!(f, "I'm not allowed to speak in that channel"),
+            JoinError::Full => write!(f, "That channel is full"),
+        }
+    }
+}
 
-    let channel_id = guild
-        .read()
+/// Finds a voice channel by mention, id or name, close names included.
+fn find_voice_channel(guild: &Guild, query: &str) -> Result<ChannelId, JoinError> {
+    if let Some(channel_id) = parse_channel(query).or_else(|| query.parse().ok()) {
+        return match guild.channels.get(&ChannelId(channel_id)) {
+            Some(channel) if channel.read().kind == ChannelType::Voice => Ok(ChannelId(channel_id)),
+            Some(_) => Err(JoinError::NotVoice),
+            None => Err(JoinError::NotFound(query.to_string())),
+        };
+    }
+
+    let voice_channels: Vec<(ChannelId, String)> = guild
+        .channels
+        .values()
+        .map(|channel| channel.read())
+        .filter(|channel| channel.kind == ChannelType::Voice)
+        .map(|channel| (channel.id, channel.name.clone()))
+        .collect();
+
+    match matching::best_match(query, &voice_channels) {
+        Match::Found(channel_id) => Ok(channel_id),
+        Match::Ambiguous(names) => Err(JoinError::Ambiguous(names)),
+        Match::NotFound => Err(JoinError::NotFound(query.to_string())),
+    }
+}
+
+/// Checks the bot can connect and speak in a channel, and that whoever asked
+/// it to join could connect there too.
+fn check_voice_access(
+    guild: &Guild,
+    channel_id: ChannelId,
+    bot_id: UserId,
+    author_id: UserId,
+) -> Result<(), JoinError> {
+    if !guild
+        .user_permissions_in(channel_id, author_id)
+        .contains(Permissions::CONNECT)
+    {
+        return Err(JoinError::AuthorCantConnect);
+    }
+
+    let permissions = guild.user_permissions_in(channel_id, bot_id);
+    if !permissions.contains(Permissions::CONNECT) {
+        return Err(JoinError::CantConnect);
+    }
+
+    if !permissions.contains(Permissions::SPEAK) {
+        return Err(JoinError::CantSpeak);
+    }
+
+    let limit = guild
+        .channels
+        .get(&channel_id)
+        .and_then(|channel| channel.read().user_limit)
+        .unwrap_or(0);
+    let connected = guild
         .voice_states
-        .get(&msg.author.id)
-        .and_then(|voice_state| voice_state.channel_id);
+        .values()
+        .filter(|state| state.channel_id == Some(channel_id) && state.user_id != bot_id)
+        .count() as u64;
+    if limit > 0 && connected >= limit && !permissions.contains(Permissions::MOVE_MEMBERS) {
+        return Err(JoinError::Full);
+    }
 
-    let connect_to = match channel_id {
-        Some(channel) => channel,
-        None => {
-            bot::check_sending_message(msg.reply(&ctx, "Not in a voice channel"));
+    Ok(())
+}
 
-            return Ok(());
-        }
+#[command]
+#[description("Join a voice channel, the one you are connected to by default.")]
+#[usage("[channel name|id|mention]")]
+#[example("join raid")]
+#[only_in(guilds)]
+fn join(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+    let guild = msg.guild(&ctx.cache).ok_or(BotError::GuildOnly)?;
+
+    let guild_id = guild.read().id;
+    let bot_id = ctx.cache.read().user.id;
+
+    let connect_to = {
+        let guild = guild.read();
+        let channel = if args.is_empty() {
+            guild
+                .voice_states
+                .get(&msg.author.id)
+                .and_then(|voice_state| voice_state.channel_id)
+                .ok_or(JoinError::NotConnected)
+        } else {
+            find_voice_channel(&guild, args.rest().trim())
+        };
+
+        channel.and_then(|channel_id| {
+            check_voice_access(&guild, channel_id, bot_id, msg.author.id).map(|_| channel_id)
+        })?
     };
 
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<VoiceManager>()
-        .cloned()
-        .expect("Expected VoiceManager in ShareMap.");
+    connect(&ctx.data, &ctx.http, guild_id, connect_to)?;
+    bot::check_sending_message(
+        msg.channel_id
+            .say(&ctx.http, format!("Joined {}", connect_to.mention())),
+    );
+
+    Ok(())
+}
+
+/// Joins a voice channel, or moves to it, listening to the users in it.
+pub fn connect(
+    data: &RwLock<ShareMap>,
+    http: &Arc<Http>,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> BotResult {
+    let activity_lock = context::state::<VoiceActivity>(data)?;
+    let auto_lock = context::state::<AutoVoice>(data)?;
+    let receiver = bot::Receiver::new(guild_id, data, Arc::clone(http))?;
+
+    let manager_lock = context::state::<VoiceManager>(data)?;
     let mut manager = manager_lock.lock();
+    let handler = manager
+        .join(guild_id, channel_id)
+        .ok_or("Error joining the channel")?;
 
-    //if manager.join(guild_id, connect_to).is_some() {
-    if let Some(handler) = manager.join(guild_id, connect_to) {
-        //handler.listen(Some(Box::new(bot::Receiver::new())));
-        bot::check_sending_message(
-            msg.channel_id
-                .say(&ctx.http, &format!("Joined {}", connect_to.mention())),
-        );
-    } else {
-        bot::check_sending_message(msg.channel_id.say(&ctx.http, "Error joining the channel"));
-    }
+    activity_lock.lock().start_session(guild_id, channel_id);
+    handler.listen(Some(Box::new(receiver)));
+    auto_lock.lock().joined(guild_id, channel_id);
 
     Ok(())
 }
 
 #[command]
+#[only_in(guilds)]
 fn leave(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild_id = match ctx.cache.read().guild_channel(msg.channel_id) {
-        Some(channel) => channel.read().guild_id,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
+    let guild_id = context::guild_id(msg)?;
 
-            return Ok(());
-        }
-    };
+    disconnect(&ctx.data, &ctx.http, guild_id)?;
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, "Left voice channel"));
+
+    Ok(())
+}
+
+/// Leaves the guild's voice channel, stopping everything going on in it.
+pub fn disconnect(data: &RwLock<ShareMap>, http: &Arc<Http>, guild_id: GuildId) -> BotResult {
+    context::state::<CountdownManager>(data)?
+        .lock()
+        .cancel_voice(guild_id);
 
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<VoiceManager>()
-        .cloned()
-        .expect("Expected VoiceManager in ShareMap.");
+    // the playback manager locks the voice manager itself, so clear the queue first.
+    context::state::<PlaybackManager>(data)?
+        .lock()
+        .stop(guild_id);
+    context::state::<AutoVoice>(data)?.lock().left(guild_id);
+
+    let manager_lock = context::state::<VoiceManager>(data)?;
     let mut manager = manager_lock.lock();
-    let has_handler = manager.get(guild_id).is_some();
+    if manager.get(guild_id).is_none() {
+        return Err(BotError::NotInVoice);
+    }
 
-    if has_handler {
-        manager.remove(guild_id);
-        bot::check_sending_message(msg.channel_id.say(&ctx.http, "Left voice channel"));
-    } else {
-        bot::check_sending_message(msg.reply(&ctx, "Not in a voice channel"));
+    manager.remove(guild_id);
+    context::state::<VoiceActivity>(data)?
+        .lock()
+        .end_session(guild_id);
+    context::state::<SpeechManager>(data)?.lock().stop(guild_id);
+
+    let recording = context::state::<RecordingManager>(data)?
+        .lock()
+        .stop(guild_id);
+    if let Some(recording) = recording {
+        recording::spawn_finish(Arc::clone(http), recording, "left the channel");
     }
 
     Ok(())
 }
 
 #[command]
-fn mute(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild_id = match ctx.cache.read().guild_channel(msg.channel_id) {
-        Some(channel) => channel.read().guild_id,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
+#[only_in(guilds)]
+#[description("Shows who spoke how long since the bot joined its voice channel.")]
+fn voicestats(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    let activity_lock = context::state::<VoiceActivity>(&ctx.data)?;
+    let activity = activity_lock.lock();
+    let session = activity.session(guild_id).ok_or(BotError::NotInVoice)?;
+
+    let listening = (Utc::now() - session.started).to_std().unwrap_or_default();
+    let mut message_builder = serenity::utils::MessageBuilder::new();
+    message_builder
+        .push_bold("Voice activity in ")
+        .mention(&session.channel_id)
+        .push_line(format!(
+            " for the last {}",
+            duration::format(Duration::from_secs(listening.as_secs()))
+        ));
+
+    let talk_time = session.talk_time();
+    if talk_time.is_empty() {
+        message_builder.push_italic_line("Nobody spoke yet");
+    }
 
-            return Ok(());
-        }
-    };
+    for (position, (user_id, talked)) in talk_time.iter().enumerate() {
+        let name = match ctx.cache.read().user(*user_id) {
+            Some(user) => user.read().name.clone(),
+            None => user_id.to_string(),
+        };
+
+        message_builder.push_line_safe(format!(
+            "{}. {}: {}",
+            position + 1,
+            name,
+            duration::format(Duration::from_secs(talked.as_secs()))
+        ));
+    }
 
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<VoiceManager>()
-        .cloned()
-        .expect("Expected VoiceManager in ShareMap.");
-    let mut manager = manager_lock.lock();
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, message_builder.build()));
 
-    let handler = match manager.get_mut(guild_id) {
-        Some(handler) => handler,
-        None => {
-            bot::check_sending_message(msg.reply(&ctx, "Not in a voice channel"));
+    Ok(())
+}
 
-            return Ok(());
-        }
-    };
+#[command]
+#[only_in(guilds)]
+fn mute(ctx: &mut Context, msg: &Message) -> CommandResult {
+    let guild_id = context::guild_id(msg)?;
+
+    let manager_lock = context::state::<VoiceManager>(&ctx.data)?;
+    let mut manager = manager_lock.lock();
+    let handler = manager.get_mut(guild_id).ok_or(BotError::NotInVoice)?;
 
     if handler.self_mute {
         bot::check_sending_message(msg.channel_id.say(&ctx.http, "Already muted"));
@@ -307,60 +580,26 @@ fn mute(ctx: &mut Context, msg: &Message) -> CommandResult {
 #[command]
 #[only_in(guilds)]
 fn unmute(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild_id = match ctx.cache.read().guild_channel(msg.channel_id) {
-        Some(channel) => channel.read().guild_id,
-        None => {
-            bot::check_sending_message(msg.channel_id.say(&ctx.http, "Error finding channel info"));
+    let guild_id = context::guild_id(msg)?;
 
-            return Ok(());
-        }
-    };
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<VoiceManager>()
-        .cloned()
-        .expect("Expected VoiceManager in ShareMap.");
+    let manager_lock = context::state::<VoiceManager>(&ctx.data)?;
     let mut manager = manager_lock.lock();
+    let handler = manager.get_mut(guild_id).ok_or(BotError::NotInVoice)?;
 
-    if let Some(handler) = manager.get_mut(guild_id) {
-        handler.mute(false);
-        bot::check_sending_message(msg.channel_id.say(&ctx.http, "Unmuted"));
-    } else {
-        bot::check_sending_message(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to unmute in"),
-        );
-    }
+    handler.mute(false);
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, "Unmuted"));
 
     Ok(())
 }
 
 #[command]
+#[only_in(guilds)]
 fn deafen(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild_id = match ctx.cache.read().guild_channel(msg.channel_id) {
-        Some(channel) => channel.read().guild_id,
-        None => {
-            bot::check_sending_message(
-                msg.channel_id
-                    .say(&ctx.http, "Groups and DMs not supported"),
-            );
+    let guild_id = context::guild_id(msg)?;
 
-            return Ok(());
-        }
-    };
-
-    let manager_lock = ctx.data.read().get::<VoiceManager>().cloned().unwrap();
+    let manager_lock = context::state::<VoiceManager>(&ctx.data)?;
     let mut manager = manager_lock.lock();
-
-    let handler = match manager.get_mut(guild_id) {
-        Some(handler) => handler,
-        None => {
-            bot::check_sending_message(msg.reply(&ctx, "Not in a voice channel"));
-
-            return Ok(());
-        }
-    };
+    let handler = manager.get_mut(guild_id).ok_or(BotError::NotInVoice)?;
 
     if handler.self_deaf {
         bot::check_sending_message(msg.channel_id.say(&ctx.http, "Already deafened"));
@@ -374,33 +613,16 @@ fn deafen(ctx: &mut Context, msg: &Message) -> CommandResult {
 }
 
 #[command]
+#[only_in(guilds)]
 fn undeafen(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let guild_id = match ctx.cache.read().guild_channel(msg.channel_id) {
-        Some(channel) => channel.read().guild_id,
-        None => {
-            bot::check_sending_message(msg.channel_id.say(&ctx.http, "Error finding channel info"));
-
-            return Ok(());
-        }
-    };
+    let guild_id = context::guild_id(msg)?;
 
-    let manager_lock = ctx
-        .data
-        .read()
-        .get::<VoiceManager>()
-        .cloned()
-        .expect("Expected VoiceManager in ShareMap.");
+    let manager_lock = context::state::<VoiceManager>(&ctx.data)?;
     let mut manager = manager_lock.lock();
+    let handler = manager.get_mut(guild_id).ok_or(BotError::NotInVoice)?;
 
-    if let Some(handler) = manager.get_mut(guild_id) {
-        handler.deafen(false);
-        bot::check_sending_message(msg.channel_id.say(&ctx.http, "Undeafened"));
-    } else {
-        bot::check_sending_message(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to undeafen in"),
-        );
-    }
+    handler.deafen(false);
+    bot::check_sending_message(msg.channel_id.say(&ctx.http, "Undeafened"));
 
     Ok(())
 }
@@ -0,0 +1,867 @@
+//! Settings of the bot, read at startup from a TOML file with env var
+//! overrides.
+//!
+//! The file is `config.toml`, or the one in the `CONFIG_FILE` env var, and
+//! can be left out entirely when the env vars cover what's needed, see
+//! `config.example.toml`. Every problem found is reported at once, so a bad
+//! deploy is fixed in one go.
+
+use crate::duration::{self, MAX_COUNTDOWN};
+use crate::speech::DEFAULT_WAKE_WORD;
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Longest countdown that can be configured, a day.
+pub const MAX_COUNTDOWN_LIMIT: u64 = 86_400;
+
+/// Shortest admin API or dashboard token accepted, so it can't be guessed.
+const MIN_TOKEN_LEN: usize = 16;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bot: Bot,
+    pub http: HttpServer,
+    pub limits: Limits,
+    pub voice: Voice,
+    pub countdown: Countdown,
+    pub speech: Speech,
+    pub storage: Storage,
+    pub tts: Tts,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bot {
+    pub token: String,
+    pub prefix: String,
+    /// `env_logger` filters, like `m_bot=debug,serenity=warn`.
+    pub log_level: String,
+    /// `text`, or `json` for one JSON object per line.
+    pub log_format: String,
+    /// See `Sharding::parse`.
+    pub shards: String,
+    /// Zone of the times of day given without one, like `Europe/Lisbon`.
+    pub timezone: String,
+}
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            prefix: String::from("."),
+            log_level: String::from("m_bot=info,serenity=warn"),
+            log_format: String::from("text"),
+            shards: String::from("1"),
+            timezone: String::from("UTC"),
+        }
+    }
+}
+
+impl Bot {
+    /// UTC if the zone is unknown, which `Config::validate` reports.
+    pub fn timezone(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+}
+
+/// Which shards this process runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sharding {
+    /// As many as Discord recommends.
+    Auto,
+    /// All of them.
+    Total(u64),
+    /// Some of them, the others run by other processes.
+    Range { first: u64, last: u64, total: u64 },
+}
+
+impl Sharding {
+    /// Parses `auto`, a number of shards like `4`, or a range of them out of
+    /// a total like `0-3/8`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let invalid = || {
+            format!(
+                "`{}` isn't `auto`, a number of shards like `4` or a range like `0-3/8`",
+                text
+            )
+        };
+        let number = |n: &str| n.trim().parse::<u64>().map_err(|_| invalid());
+
+        if text.eq_ignore_ascii_case("auto") {
+            return Ok(Sharding::Auto);
+        }
+
+        let (range, total) = match text.split_once('/') {
+            Some((range, total)) => (range, total),
+            None => match number(text)? {
+                0 => return Err(String::from("there must be at least one shard")),
+                total => return Ok(Sharding::Total(total)),
+            },
+        };
+        let (first, last) = range.split_once('-').ok_or_else(invalid)?;
+        let (first, last, total) = (number(first)?, number(last)?, number(total)?);
+        if first > last || last >= total {
+            return Err(format!(
+                "`{}` must have its first shard up to its last, which is below the total",
+                text
+            ));
+        }
+
+        Ok(Sharding::Range { first, last, total })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpServer {
+    /// Address of the server for `/healthz`, `/readyz` and `/metrics`.
+    pub bind: String,
+    /// Bearer token of the admin API under `/api`, which is off without one.
+    pub admin_token: Option<String>,
+    /// Token of the `/dashboard` page, given as `?token=`, which is off
+    /// without one. Kept apart from `admin_token` as it ends up in URLs.
+    pub dashboard_token: Option<String>,
+}
+
+impl Default for HttpServer {
+    fn default() -> Self {
+        Self {
+            bind: String::from("0.0.0.0:80"),
+            admin_token: None,
+            dashboard_token: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Longest countdown `time` and `vtime` accept.
+    pub max_countdown_secs: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_countdown_secs: MAX_COUNTDOWN.as_secs(),
+        }
+    }
+}
+
+impl Limits {
+    pub fn max_countdown(&self) -> Duration {
+        Duration::from_secs(self.max_countdown_secs)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Voice {
+    /// How long the bot stays alone in a channel, like `5m`, `off` or 0 to
+    /// stay.
+    pub idle_timeout: String,
+    /// Users followed into voice.
+    pub follow_users: Vec<u64>,
+    /// Members of these roles are followed into voice.
+    pub follow_roles: Vec<u64>,
+}
+
+impl Default for Voice {
+    fn default() -> Self {
+        Self {
+            idle_timeout: String::from("5m"),
+            follow_users: Vec::new(),
+            follow_roles: Vec::new(),
+        }
+    }
+}
+
+impl Voice {
+    /// `None` never leaves.
+    pub fn idle_timeout(&self) -> Result<Option<Duration>, duration::DurationError> {
+        if self.idle_timeout.trim().eq_ignore_ascii_case("off") {
+            return Ok(None);
+        }
+
+        let now = Utc::now().with_timezone(&Tz::UTC);
+        match duration::parse(&self.idle_timeout, now)? {
+            timeout if timeout.as_secs() == 0 => Ok(None),
+            timeout => Ok(Some(timeout)),
+        }
+    }
+}
+
+/// Which seconds of a voice countdown are announced, see
+/// `AnnouncementPolicy`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Countdown {
+    /// Seconds between announcements while above a minute.
+    pub minute_interval: u32,
+    /// Seconds between announcements below a minute.
+    pub seconds_interval: u32,
+    /// Every one of the last seconds is announced.
+    pub final_seconds: u32,
+    /// Said at zero, `sound:<clip>` plays a soundboard clip instead.
+    pub finale: String,
+}
+
+impl Default for Countdown {
+    fn default() -> Self {
+        Self {
+            minute_interval: 60,
+            seconds_interval: 10,
+            final_seconds: 10,
+            finale: String::from("go!"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Speech {
+    /// Recognizer run for each speaker, split on whitespace. Voice commands
+    /// are off without one.
+    pub command: Option<String>,
+    pub wake_word: String,
+}
+
+impl Default for Speech {
+    fn default() -> Self {
+        Self {
+            command: None,
+            wake_word: String::from(DEFAULT_WAKE_WORD),
+        }
+    }
+}
+
+impl Speech {
+    pub fn command(&self) -> Option<Vec<String>> {
+        self.command
+            .as_ref()
+            .map(|c| c.split_whitespace().map(String::from).collect())
+    }
+}
+
+/// Where the state that survives restarts is kept.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Storage {
+    /// Folder of the files and folders below that aren't set.
+    pub data_dir: String,
+    /// The `sounds` folder inside the closest `resources` folder if unset.
+    pub sounds_dir: Option<String>,
+    pub recordings_dir: Option<String>,
+    pub reminders_file: Option<String>,
+    pub roll_calls_file: Option<String>,
+    pub settings_file: Option<String>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            data_dir: String::from("data"),
+            sounds_dir: None,
+            recordings_dir: None,
+            reminders_file: None,
+            roll_calls_file: None,
+            settings_file: None,
+        }
+    }
+}
+
+impl Storage {
+    pub fn sounds_dir(&self) -> PathBuf {
+        match &self.sounds_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => find_folder::Search::KidsThenParents(3, 5)
+                .for_folder("resources")
+                .map(|resources| resources.join("sounds"))
+                .unwrap_or_else(|_| PathBuf::from("resources/sounds")),
+        }
+    }
+
+    pub fn recordings_dir(&self) -> PathBuf {
+        self.data_path(&self.recordings_dir, "recordings")
+    }
+
+    pub fn reminders_file(&self) -> PathBuf {
+        self.data_path(&self.reminders_file, "reminders.json")
+    }
+
+    pub fn roll_calls_file(&self) -> PathBuf {
+        self.data_path(&self.roll_calls_file, "roll_calls.json")
+    }
+
+    pub fn settings_file(&self) -> PathBuf {
+        self.data_path(&self.settings_file, "settings.json")
+    }
+
+    /// `path` if set, otherwise `name` inside the data folder.
+    fn data_path(&self, path: &Option<String>, name: &str) -> PathBuf {
+        match path {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&self.data_dir).join(name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tts {
+    pub voicerss: VoiceRss,
+    pub azure: Azure,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceRss {
+    /// Without one, speech from VoiceRSS is unavailable.
+    pub key: Option<String>,
+    pub endpoint: String,
+}
+
+impl Default for VoiceRss {
+    fn default() -> Self {
+        Self {
+            key: None,
+            endpoint: String::from("http://api.voicerss.org/"),
+        }
+    }
+}
+
+/// Azure Cognitive Services speech, all of it or none must be set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Azure {
+    pub key: Option<String>,
+    pub token_endpoint: Option<String>,
+    pub tts_endpoint: Option<String>,
+}
+
+impl Azure {
+    pub fn is_configured(&self) -> bool {
+        self.key.is_some() && self.token_endpoint.is_some() && self.tts_endpoint.is_some()
+    }
+}
+
+fn is_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Parses the env var `key` into `value` if `var` finds it, reporting it
+/// otherwise.
+fn parse_var<T, F>(var: &F, key: &str, value: &mut T, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    F: Fn(&str) -> Option<String>,
+{
+    if let Some(v) = var(key) {
+        match v.trim().parse() {
+            Ok(v) => *value = v,
+            Err(_) => errors.push(format!("{} `{}` isn't a number", key, v)),
+        }
+    }
+}
+
+/// Parses a comma separated list of ids, like `FOLLOW_USERS`.
+fn ids(key: &str, list: &str) -> Result<Vec<u64>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .map_err(|_| format!("{} `{}` isn't a list of ids like `1234,5678`", key, list))
+        })
+        .collect()
+}
+
+impl Config {
+    /// Reads the config file and env vars, see the module docs.
+    pub fn load() -> Result<Self, Vec<String>> {
+        let (path, required) = match std::env::var("CONFIG_FILE") {
+            Ok(path) => (path, true),
+            Err(_) => (String::from("config.toml"), false),
+        };
+
+        let mut errors = Vec::new();
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).unwrap_or_else(|why| {
+                errors.push(format!("{}: {}", path, why));
+
+                Self::default()
+            }),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound && !required => Self::default(),
+            Err(e) => {
+                errors.push(format!("{}: {}", path, e));
+
+                Self::default()
+            }
+        };
+
+        errors.extend(config.apply_env(|key| std::env::var(key).ok()));
+        errors.extend(config.validate());
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Overrides the settings with the env vars `var` finds, returning the
+    /// ones that couldn't be parsed.
+    pub fn apply_env<F>(&mut self, var: F) -> Vec<String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut errors = Vec::new();
+        let set = |key: &str, value: &mut String| {
+            if let Some(v) = var(key) {
+                *value = v;
+            }
+        };
+
+        set("BOT_TOKEN", &mut self.bot.token);
+        set("BOT_PREFIX", &mut self.bot.prefix);
+        set("RUST_LOG", &mut self.bot.log_level);
+        set("LOG_FORMAT", &mut self.bot.log_format);
+        set("SHARDS", &mut self.bot.shards);
+        set("TIMEZONE", &mut self.bot.timezone);
+        set("HTTP_BIND", &mut self.http.bind);
+        set("VOICE_IDLE_TIMEOUT", &mut self.voice.idle_timeout);
+        set("COUNTDOWN_FINALE", &mut self.countdown.finale);
+        set("SPEECH_WAKE_WORD", &mut self.speech.wake_word);
+        set("DATA_DIR", &mut self.storage.data_dir);
+        set("VOICERSS_ENDPOINT", &mut self.tts.voicerss.endpoint);
+
+        let optional = [
+            ("HTTP_ADMIN_TOKEN", &mut self.http.admin_token),
+            ("HTTP_DASHBOARD_TOKEN", &mut self.http.dashboard_token),
+            ("SPEECH_COMMAND", &mut self.speech.command),
+            ("SOUNDS_DIR", &mut self.storage.sounds_dir),
+            ("RECORDINGS_DIR", &mut self.storage.recordings_dir),
+            ("REMINDERS_FILE", &mut self.storage.reminders_file),
+            ("ROLL_CALLS_FILE", &mut self.storage.roll_calls_file),
+            ("SETTINGS_FILE", &mut self.storage.settings_file),
+            ("VOICERSS_TOKEN", &mut self.tts.voicerss.key),
+            ("AZURE_COGNITIVE_KEY", &mut self.tts.azure.key),
+            (
+                "AZURE_COGNITIVE_TOKEN_ENDPOINT",
+                &mut self.tts.azure.token_endpoint,
+            ),
+            (
+                "AZURE_COGNITIVE_TTS_ENDPOINT",
+                &mut self.tts.azure.tts_endpoint,
+            ),
+        ];
+        for (key, value) in optional {
+            if let Some(v) = var(key) {
+                *value = Some(v);
+            }
+        }
+
+        parse_var(
+            &var,
+            "MAX_COUNTDOWN_SECS",
+            &mut self.limits.max_countdown_secs,
+            &mut errors,
+        );
+
+        let lists = [
+            ("FOLLOW_USERS", &mut self.voice.follow_users),
+            ("FOLLOW_ROLES", &mut self.voice.follow_roles),
+        ];
+        for (key, value) in lists {
+            if let Some(v) = var(key) {
+                match ids(key, &v) {
+                    Ok(ids) => *value = ids,
+                    Err(why) => errors.push(why),
+                }
+            }
+        }
+
+        let countdown = [
+            (
+                "COUNTDOWN_MINUTE_INTERVAL",
+                &mut self.countdown.minute_interval,
+            ),
+            (
+                "COUNTDOWN_SECONDS_INTERVAL",
+                &mut self.countdown.seconds_interval,
+            ),
+            ("COUNTDOWN_FINAL_SECONDS", &mut self.countdown.final_seconds),
+        ];
+        for (key, value) in countdown {
+            parse_var(&var, key, value, &mut errors);
+        }
+
+        errors
+    }
+
+    /// Everything wrong with the settings.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.bot.token.trim().is_empty() {
+            errors.push(String::from("bot.token is missing, or set BOT_TOKEN"));
+        }
+
+        if self.bot.prefix.is_empty() || self.bot.prefix.contains(char::is_whitespace) {
+            errors.push(format!(
+                "bot.prefix `{}` must be non empty, without spaces",
+                self.bot.prefix
+            ));
+        }
+
+        if self.bot.log_level.trim().is_empty() {
+            errors.push(String::from("bot.log_level is empty"));
+        }
+
+        if self.bot.log_format != "text" && self.bot.log_format != "json" {
+            errors.push(format!(
+                "bot.log_format `{}` must be `text` or `json`",
+                self.bot.log_format
+            ));
+        }
+
+        if let Err(why) = Sharding::parse(&self.bot.shards) {
+            errors.push(format!("bot.shards {}", why));
+        }
+
+        if self.bot.timezone.parse::<Tz>().is_err() {
+            errors.push(format!(
+                "bot.timezone `{}` isn't a timezone like `Europe/Lisbon` or `UTC`",
+                self.bot.timezone
+            ));
+        }
+
+        if self.http.bind.parse::<SocketAddr>().is_err() {
+            errors.push(format!(
+                "http.bind `{}` isn't an address like 0.0.0.0:80",
+                self.http.bind
+            ));
+        }
+
+        let tokens = [
+            ("admin_token", &self.http.admin_token),
+            ("dashboard_token", &self.http.dashboard_token),
+        ];
+        for (key, token) in tokens.iter() {
+            if token
+                .as_ref()
+                .is_some_and(|t| t.trim().len() < MIN_TOKEN_LEN)
+            {
+                errors.push(format!(
+                    "http.{} must be at least {} characters",
+                    key, MIN_TOKEN_LEN
+                ));
+            }
+        }
+
+        if self.limits.max_countdown_secs == 0
+            || self.limits.max_countdown_secs > MAX_COUNTDOWN_LIMIT
+        {
+            errors.push(format!(
+                "limits.max_countdown_secs must be between 1 and {}",
+                MAX_COUNTDOWN_LIMIT
+            ));
+        }
+
+        if let Err(why) = self.voice.idle_timeout() {
+            errors.push(format!(
+                "voice.idle_timeout `{}` isn't a duration like `5m`, or `off`: {}",
+                self.voice.idle_timeout, why
+            ));
+        }
+
+        let intervals = [
+            ("minute_interval", self.countdown.minute_interval),
+            ("seconds_interval", self.countdown.seconds_interval),
+            ("final_seconds", self.countdown.final_seconds),
+        ];
+        for (key, value) in intervals.iter() {
+            if *value == 0 {
+                errors.push(format!("countdown.{} must be above 0", key));
+            }
+        }
+
+        let finale = self.countdown.finale.trim();
+        if finale.is_empty() || finale.strip_prefix("sound:") == Some("") {
+            errors.push(String::from(
+                "countdown.finale must be a phrase, or `sound:<clip>`",
+            ));
+        }
+
+        if self.speech.command().is_some_and(|c| c.is_empty()) {
+            errors.push(String::from(
+                "speech.command is empty, leave it out instead",
+            ));
+        }
+
+        if self.speech.wake_word.trim().is_empty() {
+            errors.push(String::from("speech.wake_word is empty"));
+        }
+
+        if self.storage.data_dir.trim().is_empty() {
+            errors.push(String::from("storage.data_dir is empty"));
+        }
+
+        if let Some(dir) = &self.storage.sounds_dir {
+            if !Path::new(dir).is_dir() {
+                errors.push(format!("storage.sounds_dir `{}` isn't a folder", dir));
+            }
+        }
+
+        let paths = [
+            ("recordings_dir", &self.storage.recordings_dir),
+            ("reminders_file", &self.storage.reminders_file),
+            ("roll_calls_file", &self.storage.roll_calls_file),
+            ("settings_file", &self.storage.settings_file),
+        ];
+        for (key, path) in paths.iter() {
+            if path.as_ref().is_some_and(|p| p.trim().is_empty()) {
+                errors.push(format!("storage.{} is empty, leave it out instead", key));
+            }
+        }
+
+        if !is_url(&self.tts.voicerss.endpoint) {
+            errors.push(format!(
+                "tts.voicerss.endpoint `{}` isn't an http(s) URL",
+                self.tts.voicerss.endpoint
+            ));
+        }
+
+        let azure = &self.tts.azure;
+        let azure_keys = [
+            ("key", &azure.key),
+            ("token_endpoint", &azure.token_endpoint),
+            ("tts_endpoint", &azure.tts_endpoint),
+        ];
+        if azure_keys.iter().any(|(_, v)| v.is_some()) {
+            for (key, value) in azure_keys.iter() {
+                match value {
+                    None => errors.push(format!(
+                        "tts.azure.{} is missing, Azure needs all of key, token_endpoint and tts_endpoint",
+                        key
+                    )),
+                    Some(url) if *key != "key" && !is_url(url) => {
+                        errors.push(format!("tts.azure.{} `{}` isn't an http(s) URL", key, url))
+                    }
+                    Some(_) => (),
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+static CURRENT: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+
+/// The settings in use, the defaults until `set` is called.
+pub fn current() -> Arc<Config> {
+    match CURRENT.read() {
+        Ok(current) => current.clone().unwrap_or_default(),
+        Err(_) => Arc::default(),
+    }
+}
+
+pub fn set(config: Config) {
+    if let Ok(mut current) = CURRENT.write() {
+        *current = Some(Arc::new(config));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn parses_toml_with_defaults() {
+        let config = Config::parse(
+            r#"
+            [bot]
+            token = "secret"
+            prefix = "!"
+
+            [tts.voicerss]
+            key = "voice"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.bot.token, "secret");
+        assert_eq!(config.bot.prefix, "!");
+        assert_eq!(config.http.bind, "0.0.0.0:80");
+        assert_eq!(config.limits.max_countdown(), MAX_COUNTDOWN);
+        assert_eq!(config.bot.timezone(), Tz::UTC);
+        assert_eq!(
+            config.voice.idle_timeout(),
+            Ok(Some(Duration::from_secs(300)))
+        );
+        assert_eq!(config.speech.command(), None);
+        assert_eq!(
+            config.storage.reminders_file(),
+            Path::new("data").join("reminders.json")
+        );
+        assert_eq!(config.tts.voicerss.key.as_deref(), Some("voice"));
+        assert!(!config.tts.azure.is_configured());
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn parses_the_example() {
+        let example = Config::parse(include_str!("../config.example.toml")).unwrap();
+
+        assert_eq!(example, Config::default());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(Config::parse("[bot]\nprefx = \"!\"").is_err());
+        assert!(Config::parse("[limits]\nmax_countdown_secs = \"1h\"").is_err());
+    }
+
+    #[test]
+    fn env_vars_override_the_file() {
+        let mut config = Config::parse("[bot]\ntoken = \"file\"\nprefix = \"!\"").unwrap();
+        let errors = config.apply_env(env(&[
+            ("BOT_TOKEN", "env"),
+            ("HTTP_BIND", "127.0.0.1:8080"),
+            ("MAX_COUNTDOWN_SECS", "600"),
+            ("TIMEZONE", "Europe/Lisbon"),
+            ("VOICE_IDLE_TIMEOUT", "off"),
+            ("FOLLOW_USERS", "1, 2"),
+            ("COUNTDOWN_FINAL_SECONDS", "5"),
+            ("SPEECH_COMMAND", "python3 listen.py"),
+            ("DATA_DIR", "/var/lib/m-bot"),
+            ("SETTINGS_FILE", "/etc/m-bot/settings.json"),
+            ("AZURE_COGNITIVE_KEY", "key"),
+        ]));
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.bot.token, "env");
+        assert_eq!(config.bot.prefix, "!");
+        assert_eq!(config.http.bind, "127.0.0.1:8080");
+        assert_eq!(config.limits.max_countdown_secs, 600);
+        assert_eq!(config.bot.timezone(), chrono_tz::Europe::Lisbon);
+        assert_eq!(config.voice.idle_timeout(), Ok(None));
+        assert_eq!(config.voice.follow_users, vec![1, 2]);
+        assert_eq!(config.countdown.final_seconds, 5);
+        assert_eq!(
+            config.speech.command(),
+            Some(vec![String::from("python3"), String::from("listen.py")])
+        );
+        assert_eq!(
+            config.storage.recordings_dir(),
+            Path::new("/var/lib/m-bot").join("recordings")
+        );
+        assert_eq!(
+            config.storage.settings_file(),
+            PathBuf::from("/etc/m-bot/settings.json")
+        );
+        assert_eq!(config.tts.azure.key.as_deref(), Some("key"));
+    }
+
+    #[test]
+    fn parses_sharding() {
+        assert_eq!(Sharding::parse("auto"), Ok(Sharding::Auto));
+        assert_eq!(Sharding::parse(" 4 "), Ok(Sharding::Total(4)));
+        assert_eq!(
+            Sharding::parse("0-3/8"),
+            Ok(Sharding::Range {
+                first: 0,
+                last: 3,
+                total: 8
+            })
+        );
+        assert!(Sharding::parse("0").is_err());
+        assert!(Sharding::parse("3-0/8").is_err());
+        assert!(Sharding::parse("4-8/8").is_err());
+        assert!(Sharding::parse("0-3").is_err());
+        assert!(Sharding::parse("many").is_err());
+    }
+
+    #[test]
+    fn parses_id_lists() {
+        assert_eq!(ids("FOLLOW_USERS", "1, 2,,3"), Ok(vec![1, 2, 3]));
+        assert_eq!(ids("FOLLOW_USERS", ""), Ok(vec![]));
+        assert!(ids("FOLLOW_USERS", "1,x").is_err());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut config = Config::default();
+        let mut errors = config.apply_env(env(&[
+            ("BOT_PREFIX", "m bot"),
+            ("LOG_FORMAT", "yaml"),
+            ("SHARDS", "4-2/8"),
+            ("HTTP_BIND", "80"),
+            ("HTTP_ADMIN_TOKEN", "secret"),
+            ("HTTP_DASHBOARD_TOKEN", "dash"),
+            ("MAX_COUNTDOWN_SECS", "1h"),
+            ("FOLLOW_ROLES", "1,admins"),
+            ("COUNTDOWN_MINUTE_INTERVAL", "-1"),
+            ("TIMEZONE", "Mars/Olympus"),
+            ("VOICE_IDLE_TIMEOUT", "soon"),
+            ("COUNTDOWN_FINALE", "sound:"),
+            ("SPEECH_WAKE_WORD", " "),
+            ("SOUNDS_DIR", "/no/such/folder"),
+            ("AZURE_COGNITIVE_TTS_ENDPOINT", "azure.com"),
+        ]));
+        config.limits.max_countdown_secs = 0;
+        config.countdown.final_seconds = 0;
+        errors.extend(config.validate());
+
+        let expected = [
+            "MAX_COUNTDOWN_SECS",
+            "FOLLOW_ROLES",
+            "COUNTDOWN_MINUTE_INTERVAL",
+            "bot.token",
+            "bot.prefix",
+            "bot.log_format",
+            "bot.shards",
+            "bot.timezone",
+            "http.bind",
+            "http.admin_token",
+            "http.dashboard_token",
+            "limits.max_countdown_secs",
+            "voice.idle_timeout",
+            "countdown.final_seconds",
+            "countdown.finale",
+            "speech.wake_word",
+            "storage.sounds_dir",
+            "tts.azure.key",
+            "tts.azure.token_endpoint",
+            "tts.azure.tts_endpoint",
+        ];
+        assert_eq!(errors.len(), expected.len(), "{:?}", errors);
+        for (error, key) in errors.iter().zip(expected.iter()) {
+            assert!(error.starts_with(key), "{} should be about {}", error, key);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! What the commands share: resolving the guild, fetching shared state and
+//! the errors they report.
+//!
+//! Commands return a `BotError` with `?`, the framework's `after` hook then
+//! replies with it, so every command reports errors the same way.
+
+use serenity::http::Http;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::prelude::*;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum BotError {
+    /// Used in a DM or group.
+    GuildOnly,
+    /// The bot isn't in a voice channel of the guild.
+    NotInVoice,
+    NoRollCall,
+    /// Shared state that wasn't set up, the bot's fault rather than the
+    /// user's, so the details are only logged.
+    MissingState(&'static str),
+    /// Anything else the user should know.
+    Message(String),
+}
+
+pub type BotResult<T = ()> = Result<T, BotError>;
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BotError::GuildOnly => write!(f, "Groups and DMs not supported"),
+            BotError::NotInVoice => write!(f, "Not in a voice channel"),
+            BotError::NoRollCall => write!(f, "There's no active Roll-Call. Start one first"),
+            BotError::MissingState(_) => write!(f, "Something went wrong, try again later."),
+            BotError::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<&'static str> for BotError {
+    fn from(message: &'static str) -> Self {
+        BotError::Message(message.to_string())
+    }
+}
+
+impl From<String> for BotError {
+    fn from(message: String) -> Self {
+        BotError::Message(message)
+    }
+}
+
+pub fn guild_id(msg: &Message) -> BotResult<GuildId> {
+    msg.guild_id.ok_or(BotError::GuildOnly)
+}
+
+/// Fetches shared state from the `TypeMap`.
+pub fn state<K>(data: &RwLock<ShareMap>) -> BotResult<K::Value>
+where
+    K: TypeMapKey,
+    K::Value: Clone + Send + Sync,
+{
+    match data.read().get::<K>() {
+        Some(value) => Ok(value.clone()),
+        None => {
+            let name = std::any::type_name::<K>();
+            error!("Expected {} in ShareMap.", name);
+
+            Err(BotError::MissingState(name))
+        }
+    }
+}
+
+/// Tells a user something went wrong, like `Message::reply` does.
+pub fn report(http: &Http, channel_id: ChannelId, user_id: UserId, error: &str) {
+    crate::check_sending_message(channel_id.say(http, format!("{}: {}", user_id.mention(), error)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Missing;
+
+    impl TypeMapKey for Missing {
+        type Value = u8;
+    }
+
+    #[test]
+    fn missing_state_is_an_error() {
+        let data = RwLock::new(ShareMap::custom());
+        let error = state::<Missing>(&data).unwrap_err();
+        assert!(matches!(error, BotError::MissingState(name) if name.ends_with("Missing")));
+        assert_eq!(error.to_string(), "Something went wrong, try again later.");
+
+        data.write().insert::<Missing>(7);
+        assert_eq!(state::<Missing>(&data), Ok(7));
+    }
+}
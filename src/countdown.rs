@@ -0,0 +1,500 @@
+//! Bookkeeping for countdowns running as background tasks.
+//!
+//! The command starting a countdown registers it here and hands the returned
+//! `Countdown` to the thread running it, which polls `is_cancelled` between
+//! ticks and calls `finish_voice` or `finish_text` when done. There is at
+//! most one voice countdown per guild and one text countdown per channel.
+
+use crate::config;
+
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Longest a cancelled countdown keeps sleeping before noticing.
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Clone)]
+pub struct Countdown {
+    id: u64,
+    pub started_by: UserId,
+    pub channel_id: ChannelId,
+    pub duration: Duration,
+    /// When the first tick is due, every tick is scheduled relative to it so
+    /// a late tick doesn't push back the following ones.
+    pub starts_at: Instant,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Countdown {
+    pub fn remaining(&self) -> Duration {
+        (self.starts_at + self.duration).saturating_duration_since(Instant::now())
+    }
+
+    /// When the tick `seconds` after the start is due.
+    pub fn tick_at(&self, seconds: u32) -> Instant {
+        self.starts_at + Duration::from_secs(u64::from(seconds))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps until `deadline`, waking up regularly to check for cancellation.
+    ///
+    /// Returns false if the countdown was cancelled meanwhile.
+    pub fn sleep_until(&self, deadline: Instant) -> bool {
+        loop {
+            if self.is_cancelled() {
+                return false;
+            }
+
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                return true;
+            }
+
+            std::thread::sleep(left.min(CANCEL_POLL));
+        }
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// What is said when a countdown reaches zero.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Finale {
+    Phrase(String),
+    /// Name of a soundboard clip.
+    Sound(String),
+}
+
+/// Decides which seconds of a voice countdown are announced, and how.
+///
+/// By default: every minute while above a minute, every 10 seconds below a
+/// minute, and every second of the final 10.
+#[derive(Clone, Debug)]
+pub struct AnnouncementPolicy {
+    pub minute_interval: u32,
+    pub seconds_interval: u32,
+    pub final_seconds: u32,
+    pub finale: Finale,
+}
+
+impl Default for AnnouncementPolicy {
+    fn default() -> Self {
+        Self::from_config(&config::Countdown::default())
+    }
+}
+
+impl AnnouncementPolicy {
+    /// A finale of `sound:<clip>` plays a soundboard clip.
+    pub fn from_config(countdown: &config::Countdown) -> Self {
+        let finale = countdown.finale.trim();
+
+        Self {
+            minute_interval: countdown.minute_interval,
+            seconds_interval: countdown.seconds_interval,
+            final_seconds: countdown.final_seconds,
+            finale: match finale.strip_prefix("sound:") {
+                Some(clip) => Finale::Sound(clip.to_string()),
+                None => Finale::Phrase(finale.to_string()),
+            },
+        }
+    }
+
+    /// Whether the tick with `remaining` seconds left in a countdown of
+    /// `total` seconds is announced. The first and last ones always are.
+    pub fn announces(&self, remaining: u32, total: u32) -> bool {
+        remaining == total
+            || remaining <= self.final_seconds
+            || (remaining < 60 && remaining.is_multiple_of(self.seconds_interval))
+            || (remaining >= 60 && remaining.is_multiple_of(self.minute_interval))
+    }
+
+    /// What to say with `remaining` seconds left, `None` for the finale.
+    pub fn phrase(&self, remaining: u32) -> Option<String> {
+        if remaining == 0 {
+            return None;
+        }
+
+        if remaining <= self.final_seconds {
+            return Some(number_words(remaining));
+        }
+
+        let (minutes, seconds) = (remaining / 60, remaining % 60);
+        let mut parts = Vec::new();
+        if minutes > 0 {
+            parts.push(plural(minutes, "minute"));
+        }
+        if seconds > 0 {
+            parts.push(plural(seconds, "second"));
+        }
+
+        Some(format!("{} remaining", parts.join(" ")))
+    }
+}
+
+fn plural(value: u32, unit: &str) -> String {
+    if value == 1 {
+        format!("one {}", unit)
+    } else {
+        format!("{} {}s", number_words(value), unit)
+    }
+}
+
+/// Spells out numbers below a thousand, "forty two".
+pub fn number_words(value: u32) -> String {
+    const ONES: [&str; 20] = [
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+    const TENS: [&str; 10] = [
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+
+    match value {
+        0..=19 => ONES[value as usize].to_string(),
+        20..=99 if value.is_multiple_of(10) => TENS[(value / 10) as usize].to_string(),
+        20..=99 => format!(
+            "{} {}",
+            TENS[(value / 10) as usize],
+            ONES[(value % 10) as usize]
+        ),
+        100..=999 if value.is_multiple_of(100) => {
+            format!("{} hundred", ONES[(value / 100) as usize])
+        }
+        100..=999 => format!(
+            "{} hundred {}",
+            ONES[(value / 100) as usize],
+            number_words(value % 100)
+        ),
+        _ => value.to_string(),
+    }
+}
+
+/// How often a text countdown edits its message with `remaining` seconds
+/// left, slow enough to stay well under Discord's edit rate limits.
+pub fn text_edit_interval(remaining: u32) -> u32 {
+    match remaining {
+        0..=10 => 1,
+        11..=60 => 5,
+        61..=600 => 15,
+        _ => 60,
+    }
+}
+
+pub struct CountdownManager {
+    next_id: u64,
+    voice: HashMap<GuildId, Countdown>,
+    text: HashMap<ChannelId, Countdown>,
+    pub policy: AnnouncementPolicy,
+}
+
+impl TypeMapKey for CountdownManager {
+    type Value = Arc<Mutex<CountdownManager>>;
+}
+
+impl CountdownManager {
+    pub fn new(policy: AnnouncementPolicy) -> Self {
+        Self {
+            next_id: 0,
+            voice: HashMap::new(),
+            text: HashMap::new(),
+            policy,
+        }
+    }
+
+    fn countdown(
+        &mut self,
+        started_by: UserId,
+        channel_id: ChannelId,
+        starts_at: Instant,
+        duration: Duration,
+    ) -> Countdown {
+        self.next_id += 1;
+
+        Countdown {
+            id: self.next_id,
+            started_by,
+            channel_id,
+            duration,
+            starts_at,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Registers a voice countdown for the guild, unless one is already running.
+    pub fn start_voice(
+        &mut self,
+        guild_id: GuildId,
+        started_by: UserId,
+        channel_id: ChannelId,
+        starts_at: Instant,
+        duration: Duration,
+    ) -> Option<Countdown> {
+        if self.voice.contains_key(&guild_id) {
+            return None;
+        }
+
+        let countdown = self.countdown(started_by, channel_id, starts_at, duration);
+        self.voice.insert(guild_id, countdown.clone());
+
+        Some(countdown)
+    }
+
+    pub fn voice(&self, guild_id: GuildId) -> Option<&Countdown> {
+        self.voice.get(&guild_id)
+    }
+
+    /// Flags the guild's voice countdown to stop, returns false if none was running.
+    pub fn cancel_voice(&mut self, guild_id: GuildId) -> bool {
+        match self.voice.remove(&guild_id) {
+            Some(countdown) => {
+                countdown.cancel();
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Called by the countdown task once it ends, for whatever reason.
+    pub fn finish_voice(&mut self, guild_id: GuildId, countdown: &Countdown) {
+        if self.voice.get(&guild_id).map(|c| c.id) == Some(countdown.id) {
+            self.voice.remove(&guild_id);
+        }
+    }
+
+    /// Flags every countdown to stop, returning how many were running.
+    pub fn cancel_all(&mut self) -> usize {
+        let countdowns: Vec<Countdown> = self
+            .voice
+            .drain()
+            .map(|(_, c)| c)
+            .chain(self.text.drain().map(|(_, c)| c))
+            .collect();
+        for countdown in &countdowns {
+            countdown.cancel();
+        }
+
+        countdowns.len()
+    }
+
+    /// Registers a text countdown for the channel, unless one is already running.
+    pub fn start_text(
+        &mut self,
+        started_by: UserId,
+        channel_id: ChannelId,
+        starts_at: Instant,
+        duration: Duration,
+    ) -> Option<Countdown> {
+        if self.text.contains_key(&channel_id) {
+            return None;
+        }
+
+        let countdown = self.countdown(started_by, channel_id, starts_at, duration);
+        self.text.insert(channel_id, countdown.clone());
+
+        Some(countdown)
+    }
+
+    pub fn text(&self, channel_id: ChannelId) -> Option<&Countdown> {
+        self.text.get(&channel_id)
+    }
+
+    /// Flags the channel's text countdown to stop, returns false if none was running.
+    pub fn cancel_text(&mut self, channel_id: ChannelId) -> bool {
+        match self.text.remove(&channel_id) {
+            Some(countdown) => {
+                countdown.cancel();
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Called by the text countdown task once it ends, for whatever reason.
+    pub fn finish_text(&mut self, countdown: &Countdown) {
+        if self.text.get(&countdown.channel_id).map(|c| c.id) == Some(countdown.id) {
+            self.text.remove(&countdown.channel_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn countdown(manager: &mut CountdownManager, starts_at: Instant) -> Countdown {
+        manager
+            .start_voice(
+                GuildId(1),
+                UserId(2),
+                ChannelId(3),
+                starts_at,
+                Duration::from_secs(10),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn spells_numbers() {
+        assert_eq!(number_words(0), "zero");
+        assert_eq!(number_words(13), "thirteen");
+        assert_eq!(number_words(40), "forty");
+        assert_eq!(number_words(59), "fifty nine");
+        assert_eq!(number_words(300), "three hundred");
+        assert_eq!(number_words(342), "three hundred forty two");
+    }
+
+    #[test]
+    fn announces_minutes_tens_and_final_seconds() {
+        let policy = AnnouncementPolicy::default();
+        let announced: Vec<u32> = (0..=150)
+            .rev()
+            .filter(|r| policy.announces(*r, 150))
+            .collect();
+
+        assert_eq!(
+            announced,
+            vec![150, 120, 60, 50, 40, 30, 20, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn phrases_naturally() {
+        let policy = AnnouncementPolicy::default();
+
+        assert_eq!(
+            policy.phrase(150).unwrap(),
+            "two minutes thirty seconds remaining"
+        );
+        assert_eq!(policy.phrase(60).unwrap(), "one minute remaining");
+        assert_eq!(policy.phrase(30).unwrap(), "thirty seconds remaining");
+        assert_eq!(policy.phrase(10).unwrap(), "ten");
+        assert_eq!(policy.phrase(1).unwrap(), "one");
+        assert_eq!(policy.phrase(0), None);
+    }
+
+    #[test]
+    fn edits_text_less_often_when_far_from_zero() {
+        let edits = (0..=3600u32)
+            .filter(|r| r % text_edit_interval(*r) == 0)
+            .count();
+
+        assert_eq!(text_edit_interval(5), 1);
+        assert_eq!(text_edit_interval(45), 5);
+        assert!(edits < 120);
+    }
+
+    #[test]
+    fn text_countdowns_are_per_channel() {
+        let mut manager = CountdownManager::new(AnnouncementPolicy::default());
+        let duration = Duration::from_secs(10);
+        let first = manager
+            .start_text(UserId(2), ChannelId(3), Instant::now(), duration)
+            .unwrap();
+
+        assert!(manager
+            .start_text(UserId(2), ChannelId(3), Instant::now(), duration)
+            .is_none());
+        assert!(manager
+            .start_text(UserId(2), ChannelId(4), Instant::now(), duration)
+            .is_some());
+
+        assert!(manager.cancel_text(ChannelId(3)));
+        assert!(first.is_cancelled());
+        manager.finish_text(&first);
+        assert!(manager.text(ChannelId(3)).is_none());
+        assert!(manager.text(ChannelId(4)).is_some());
+    }
+
+    #[test]
+    fn cancels_every_countdown() {
+        let mut manager = CountdownManager::new(AnnouncementPolicy::default());
+        let voice = countdown(&mut manager, Instant::now());
+        let text = manager
+            .start_text(
+                UserId(2),
+                ChannelId(4),
+                Instant::now(),
+                Duration::from_secs(10),
+            )
+            .unwrap();
+
+        assert_eq!(manager.cancel_all(), 2);
+        assert!(voice.is_cancelled() && text.is_cancelled());
+        assert!(manager.voice(GuildId(1)).is_none());
+        assert!(manager.text(ChannelId(4)).is_none());
+        assert_eq!(manager.cancel_all(), 0);
+    }
+
+    #[test]
+    fn ticks_are_anchored_to_the_start() {
+        let starts_at = Instant::now() + Duration::from_secs(1);
+        let countdown = countdown(
+            &mut CountdownManager::new(AnnouncementPolicy::default()),
+            starts_at,
+        );
+
+        assert_eq!(countdown.tick_at(0), starts_at);
+        assert_eq!(countdown.tick_at(7), starts_at + Duration::from_secs(7));
+        assert!(countdown.remaining() > Duration::from_secs(10));
+    }
+
+    #[test]
+    fn sleeps_until_due() {
+        let countdown = countdown(
+            &mut CountdownManager::new(AnnouncementPolicy::default()),
+            Instant::now(),
+        );
+        let due = Instant::now() + Duration::from_millis(30);
+
+        assert!(countdown.sleep_until(due));
+        assert!(Instant::now() >= due);
+    }
+
+    #[test]
+    fn cancelling_wakes_up_sleepers() {
+        let mut manager = CountdownManager::new(AnnouncementPolicy::default());
+        let countdown = countdown(&mut manager, Instant::now());
+        assert!(manager
+            .start_voice(
+                GuildId(1),
+                UserId(2),
+                ChannelId(3),
+                Instant::now(),
+                Duration::from_secs(1)
+            )
+            .is_none());
+
+        assert!(manager.cancel_voice(GuildId(1)));
+        assert!(!countdown.sleep_until(Instant::now() + Duration::from_secs(60)));
+        assert!(manager.voice(GuildId(1)).is_none());
+    }
+}
@@ -0,0 +1,187 @@
+//! Read-only roll call dashboard at `/dashboard`, for a second screen during
+//! rally prep. It's a single page without external assets that reloads
+//! itself every `REFRESH_SECS`.
+//!
+//! It's opened as `/dashboard?token=<http.dashboard_token>`, and answers 404
+//! when no token is configured.
+
+use crate::api;
+use crate::context;
+use crate::RollCallManager;
+
+use serenity::cache::Cache;
+use serenity::model::id::UserId;
+use serenity::prelude::*;
+use std::fmt::Write;
+use url::form_urlencoded;
+
+pub const REFRESH_SECS: u32 = 5;
+
+/// Whether a request for `url` has the token, in its `token` query parameter
+/// or an `Authorization` header like the admin API's.
+pub fn is_authorized(url: &str, authorization: Option<&str>, token: &str) -> bool {
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+
+    form_urlencoded::parse(query.as_bytes())
+        .any(|(key, value)| key == "token" && api::same_token(&value, token))
+        || api::is_authorized(authorization, token)
+}
+
+/// A roll call as shown, with names resolved.
+pub struct Row {
+    pub guild: String,
+    pub started_by: String,
+    pub requested: u16,
+    pub joined: Vec<String>,
+    pub missing: u16,
+}
+
+/// The roll calls running, by guild name.
+pub fn rows(data: &RwLock<ShareMap>, cache: &RwLock<Cache>) -> Vec<Row> {
+    let manager_lock = match context::state::<RollCallManager>(data) {
+        Ok(manager_lock) => manager_lock,
+        Err(_) => return Vec::new(),
+    };
+    let manager = manager_lock.lock();
+    let cache = cache.read();
+    let user_name = |user_id: UserId| {
+        cache
+            .user(user_id)
+            .map(|user| user.read().name.clone())
+            .unwrap_or_else(|| user_id.to_string())
+    };
+
+    let mut rows: Vec<Row> = manager
+        .list
+        .values()
+        .map(|rc| {
+            let mut joined: Vec<String> = rc.joined.iter().map(|u| user_name(*u)).collect();
+            joined.sort();
+
+            Row {
+                guild: cache
+                    .guild(rc.guild_id)
+                    .map(|guild| guild.read().name.clone())
+                    .unwrap_or_else(|| rc.guild_id.to_string()),
+                started_by: user_name(rc.call_by),
+                requested: rc.requested,
+                joined,
+                missing: rc.lack(),
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| a.guild.cmp(&b.guild));
+
+    rows
+}
+
+pub fn render(rows: &[Row]) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"{}\">\
+         <title>Roll Calls</title><style>{}</style></head><body><h1>Roll Calls</h1>",
+        REFRESH_SECS, STYLE
+    );
+
+    if rows.is_empty() {
+        html.push_str("<p class=\"empty\">No Roll Call running.</p>");
+    }
+
+    for row in rows {
+        let joined = usize::from(row.requested) - usize::from(row.missing);
+        let _ = write!(
+            html,
+            "<section><h2>{}</h2><p>Started by {}</p>\
+             <p class=\"count\"><b>{}</b> joined, <b>{}</b> missing of {}</p>\
+             <progress value=\"{}\" max=\"{}\"></progress><ul>",
+            escape(&row.guild),
+            escape(&row.started_by),
+            joined,
+            row.missing,
+            row.requested,
+            joined,
+            row.requested
+        );
+        for name in &row.joined {
+            let _ = write!(html, "<li>{}</li>", escape(name));
+        }
+        html.push_str("</ul></section>");
+    }
+
+    html.push_str("</body></html>");
+
+    html
+}
+
+const STYLE: &str = "body{font-family:sans-serif;background:#23272a;color:#fff;margin:2em}\
+section{background:#2c2f33;border-radius:8px;padding:1em 1.5em;margin-bottom:1em}\
+h2{margin:0 0 .3em}.count{font-size:1.4em}progress{width:100%;height:1.2em}\
+ul{columns:3;padding-left:1.2em}.empty{color:#99aab5;font-size:1.4em}";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_the_token() {
+        let token = "0123456789abcdef";
+        assert!(is_authorized(
+            "/dashboard?token=0123456789abcdef",
+            None,
+            token
+        ));
+        assert!(is_authorized(
+            "/dashboard?x=1&token=0123456789abcdef",
+            None,
+            token
+        ));
+        assert!(is_authorized(
+            "/dashboard",
+            Some("Bearer 0123456789abcdef"),
+            token
+        ));
+        assert!(!is_authorized(
+            "/dashboard?token=0123456789abcdeF",
+            None,
+            token
+        ));
+        assert!(!is_authorized(
+            "/dashboard?tok=0123456789abcdef",
+            None,
+            token
+        ));
+        assert!(!is_authorized("/dashboard", None, token));
+    }
+
+    #[test]
+    fn renders_roll_calls() {
+        let html = render(&[Row {
+            guild: String::from("<Lisbon> & co"),
+            started_by: String::from("nelson"),
+            requested: 10,
+            joined: vec![String::from("ana"), String::from("rui")],
+            missing: 8,
+        }]);
+
+        assert!(html.contains("content=\"5\""));
+        assert!(html.contains("<h2>&lt;Lisbon&gt; &amp; co</h2>"));
+        assert!(html.contains("<b>2</b> joined, <b>8</b> missing of 10"));
+        assert!(html.contains("<li>ana</li><li>rui</li>"));
+        assert!(!html.contains("src=") && !html.contains("href="));
+    }
+
+    #[test]
+    fn says_when_nothing_runs() {
+        assert!(render(&[]).contains("No Roll Call running."));
+    }
+}
@@ -0,0 +1,297 @@
+//! Parsing of the durations given to timer commands.
+//!
+//! Accepts plain seconds (`90`), units (`1m30s`, `2h`), clock notation
+//! (`1:30`, `1:00:00`) and a time of day (`at 21:00`, `at 21:00 Europe/Lisbon`).
+//! Times of day without a timezone are taken in the zone of `now`, see
+//! `default_timezone`.
+
+use crate::config;
+
+use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::time::Duration;
+
+/// Longest countdown `time` and `vtime` accept, unless configured otherwise.
+pub const MAX_COUNTDOWN: Duration = Duration::from_secs(3600);
+
+/// Furthest in the future a reminder can be set, 30 days.
+pub const MAX_REMINDER: Duration = Duration::from_secs(30 * 86400);
+
+#[derive(Debug, PartialEq)]
+pub enum DurationError {
+    Invalid,
+    UnknownTimezone(String),
+    Zero,
+    TooLong(Duration),
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DurationError::Invalid => write!(
+                f,
+                "Durations look like `90`, `1m30s`, `1:30`, `2h` or `at 21:00 Europe/Lisbon`."
+            ),
+            DurationError::UnknownTimezone(name) => write!(
+                f,
+                "Unknown timezone `{}`, use names like `Europe/Lisbon` or `UTC`.",
+                name
+            ),
+            DurationError::Zero => write!(f, "The duration must be longer than 0s!"),
+            DurationError::TooLong(max) => {
+                write!(f, "Supplied duration is above the max of {}!", format(*max))
+            }
+        }
+    }
+}
+
+/// The zone of times of day given without one, `bot.timezone` in the config.
+pub fn default_timezone() -> Tz {
+    config::current().bot.timezone()
+}
+
+/// The current time in the default timezone.
+pub fn now() -> DateTime<Tz> {
+    Utc::now().with_timezone(&default_timezone())
+}
+
+/// Parses `input` as a duration from `now`.
+pub fn parse(input: &str, now: DateTime<Tz>) -> Result<Duration, DurationError> {
+    let input = input.trim().to_lowercase();
+    let mut words = input.split_whitespace();
+
+    match (words.next(), words.next(), words.next(), words.next()) {
+        (Some("at"), Some(time), zone, None) => until(time, zone, now),
+        (Some(value), None, None, None) => parse_clock(value)
+            .or_else(|| parse_units(value))
+            .ok_or(DurationError::Invalid),
+        _ => Err(DurationError::Invalid),
+    }
+}
+
+/// Parses `input` like `parse`, rejecting durations of 0 or longer than
+/// `max`.
+pub fn parse_within(
+    input: &str,
+    now: DateTime<Tz>,
+    max: Duration,
+) -> Result<Duration, DurationError> {
+    match parse(input, now)? {
+        duration if duration.as_secs() == 0 => Err(DurationError::Zero),
+        duration if duration > max => Err(DurationError::TooLong(max)),
+        duration => Ok(duration),
+    }
+}
+
+/// Formats a duration in the units notation, `1h30m`, `45s`.
+pub fn format(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let parts = [
+        (seconds / 86400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+
+    let formatted: String = parts
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+
+    if formatted.is_empty() {
+        String::from("0s")
+    } else {
+        formatted
+    }
+}
+
+/// `90`, `1:30` or `1:00:00`.
+fn parse_clock(value: &str) -> Option<Duration> {
+    let mut fields = Vec::new();
+    for field in value.split(':') {
+        if field.is_empty() || !field.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        fields.push(field.parse::<u64>().ok()?);
+    }
+
+    // Only the leading field can go over 59, `90:00` is fine but `1:90` isn't
+    if fields.len() > 3 || fields[1..].iter().any(|f| *f >= 60) {
+        return None;
+    }
+
+    let seconds = fields
+        .iter()
+        .try_fold(0u64, |total, f| total.checked_mul(60)?.checked_add(*f))?;
+
+    Some(Duration::from_secs(seconds))
+}
+
+/// `2h`, `1m30s`, `1d12h`.
+fn parse_units(value: &str) -> Option<Duration> {
+    let mut seconds = 0u64;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+
+            continue;
+        }
+
+        let unit = match c {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+
+        let amount = number.parse::<u64>().ok()?;
+        seconds = seconds.checked_add(amount.checked_mul(unit)?)?;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(Duration::from_secs(seconds))
+}
+
+/// Time left until the next `time` of day, in `zone` or the zone of `now`.
+fn until(time: &str, zone: Option<&str>, now: DateTime<Tz>) -> Result<Duration, DurationError> {
+    let time = NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| DurationError::Invalid)?;
+    let zone: Tz = match zone {
+        Some(name) => name
+            .parse()
+            .or_else(|_| capitalized(name).parse())
+            .map_err(|_| DurationError::UnknownTimezone(name.to_string()))?,
+        None => now.timezone(),
+    };
+
+    let local_now = now.with_timezone(&zone);
+    let mut date = local_now.date().naive_local();
+    for _ in 0..2 {
+        let target = zone
+            .from_local_datetime(&date.and_time(time))
+            .earliest()
+            .ok_or(DurationError::Invalid)?;
+        if target > local_now {
+            let seconds = (target - local_now).num_seconds();
+
+            return Ok(Duration::from_secs(seconds as u64));
+        }
+
+        date = date.succ();
+    }
+
+    Err(DurationError::Invalid)
+}
+
+/// Timezone names are case sensitive, but the input was lowercased:
+/// `europe/lisbon` becomes `Europe/Lisbon`, `utc` becomes `UTC`.
+fn capitalized(name: &str) -> String {
+    if name.len() <= 4 {
+        return name.to_uppercase();
+    }
+
+    name.split('/')
+        .map(|part| {
+            part.split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("_")
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Tz> {
+        Tz::UTC.ymd(2020, 6, 1).and_hms(20, 0, 0)
+    }
+
+    fn secs(seconds: u64) -> Result<Duration, DurationError> {
+        Ok(Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn parses_seconds_units_and_clocks() {
+        assert_eq!(parse("90", now()), secs(90));
+        assert_eq!(parse("1m30s", now()), secs(90));
+        assert_eq!(parse("2h", now()), secs(7200));
+        assert_eq!(parse("1D12H", now()), secs(129_600));
+        assert_eq!(parse("1:30", now()), secs(90));
+        assert_eq!(parse("1:00:05", now()), secs(3605));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for input in &[
+            "", "-5", "1.5", "m", "1x", "30m5", "1:90", "1::2", "at", "at 25:00", "1 2",
+        ] {
+            assert_eq!(
+                parse(input, now()),
+                Err(DurationError::Invalid),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!(parse("at 21:00", now()), secs(3600));
+        assert_eq!(parse("at 19:00", now()), secs(23 * 3600));
+        assert_eq!(parse("at 20:00", now()), secs(24 * 3600));
+        // Lisbon is UTC+1 in June
+        assert_eq!(parse("at 22:00 Europe/Lisbon", now()), secs(3600));
+        assert_eq!(parse("at 22:00 europe/lisbon", now()), secs(3600));
+        assert_eq!(parse("at 21:00 utc", now()), secs(3600));
+        assert_eq!(
+            parse("at 21:00 Mars/Olympus", now()),
+            Err(DurationError::UnknownTimezone(String::from("mars/olympus")))
+        );
+    }
+
+    #[test]
+    fn enforces_bounds() {
+        assert_eq!(parse_within("1h", now(), MAX_COUNTDOWN), secs(3600));
+        assert_eq!(parse_within("1s", now(), MAX_COUNTDOWN), secs(1));
+        for input in &["0", "0m", "0:00"] {
+            assert_eq!(
+                parse_within(input, now(), MAX_COUNTDOWN),
+                Err(DurationError::Zero),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            parse_within("1h1s", now(), MAX_COUNTDOWN),
+            Err(DurationError::TooLong(MAX_COUNTDOWN))
+        );
+        assert_eq!(
+            DurationError::TooLong(MAX_COUNTDOWN).to_string(),
+            "Supplied duration is above the max of 1h!"
+        );
+    }
+
+    #[test]
+    fn formats_units() {
+        assert_eq!(format(Duration::from_secs(0)), "0s");
+        assert_eq!(format(Duration::from_secs(90)), "1m30s");
+        assert_eq!(format(Duration::from_secs(93_600)), "1d2h");
+    }
+}
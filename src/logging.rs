@@ -0,0 +1,212 @@
+//! Logging set up from the config: `env_logger` filters, as plain text or
+//! JSON lines, with secrets redacted.
+//!
+//! Lines logged while a command runs carry its guild, user and command, set
+//! by the framework's `before` hook and cleared by its `after` one, which run
+//! on the command's thread.
+
+use crate::config;
+
+use env_logger::fmt::Formatter;
+use log::Record;
+use serenity::model::id::{GuildId, UserId};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::Write;
+
+/// Secrets shorter than this aren't redacted, as they'd mangle ordinary
+/// text and aren't real secrets anyway.
+const MIN_SECRET_LEN: usize = 8;
+
+const REDACTED: &str = "[redacted]";
+
+/// Who a command's log lines are about.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fields {
+    pub guild_id: Option<GuildId>,
+    pub user_id: UserId,
+    pub command: String,
+}
+
+thread_local! {
+    static FIELDS: RefCell<Option<Fields>> = const { RefCell::new(None) };
+}
+
+pub fn set_fields(fields: Fields) {
+    FIELDS.with(|current| *current.borrow_mut() = Some(fields));
+}
+
+pub fn clear_fields() {
+    FIELDS.with(|current| *current.borrow_mut() = None);
+}
+
+fn fields() -> Option<Fields> {
+    FIELDS.with(|current| current.borrow().clone())
+}
+
+/// Replaces every secret in `text`.
+pub fn redact<'a>(text: &'a str, secrets: &[&str]) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for secret in secrets {
+        if secret.len() >= MIN_SECRET_LEN && text.contains(secret) {
+            text = Cow::Owned(text.replace(secret, REDACTED));
+        }
+    }
+
+    text
+}
+
+/// The secrets in the config in use.
+fn secrets(config: &config::Config) -> Vec<&str> {
+    let mut secrets = vec![config.bot.token.as_str()];
+    secrets.extend(config.http.admin_token.as_deref());
+    secrets.extend(config.http.dashboard_token.as_deref());
+    secrets.extend(config.tts.voicerss.key.as_deref());
+    secrets.extend(config.tts.azure.key.as_deref());
+
+    secrets
+}
+
+/// A log line as JSON, with the command's fields if any.
+pub fn json_line(
+    timestamp: &str,
+    record: &Record,
+    message: &str,
+    fields: Option<&Fields>,
+) -> serde_json::Value {
+    let mut line = serde_json::json!({
+        "timestamp": timestamp,
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": message,
+    });
+    if let Some(fields) = fields {
+        if let Some(guild_id) = fields.guild_id {
+            line["guild_id"] = guild_id.to_string().into();
+        }
+        line["user_id"] = fields.user_id.to_string().into();
+        line["command"] = fields.command.clone().into();
+    }
+
+    line
+}
+
+fn format(buf: &mut Formatter, record: &Record, json: bool) -> std::io::Result<()> {
+    let config = config::current();
+    let message = record.args().to_string();
+    let message = redact(&message, &secrets(&config));
+    let timestamp = buf.timestamp();
+
+    if json {
+        let line = json_line(&timestamp.to_string(), record, &message, fields().as_ref());
+        return writeln!(buf, "{}", line);
+    }
+
+    match fields() {
+        Some(fields) => writeln!(
+            buf,
+            "[{} {:<5} {}] [{}{} {}] {}",
+            timestamp,
+            record.level(),
+            record.target(),
+            fields
+                .guild_id
+                .map(|guild_id| format!("{} ", guild_id))
+                .unwrap_or_default(),
+            fields.user_id,
+            fields.command,
+            message
+        ),
+        None => writeln!(
+            buf,
+            "[{} {:<5} {}] {}",
+            timestamp,
+            record.level(),
+            record.target(),
+            message
+        ),
+    }
+}
+
+/// Starts logging with `env_logger` `filters`, as JSON lines if `json`.
+pub fn init(filters: &str, json: bool) {
+    env_logger::Builder::new()
+        .parse_filters(filters)
+        .format(move |buf, record| format(buf, record, json))
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_secrets() {
+        let secrets = ["bot-token-123", "short", ""];
+        assert_eq!(
+            redact("Bot bot-token-123 and bot-token-123", &secrets),
+            "Bot [redacted] and [redacted]"
+        );
+        assert_eq!(redact("a short message", &secrets), "a short message");
+        assert!(matches!(redact("nothing", &secrets), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn redacts_the_config_tokens() {
+        let mut config = config::Config::default();
+        config.bot.token = String::from("bot-token-123");
+        config.http.admin_token = Some(String::from("admin-token-0123456789"));
+        config.http.dashboard_token = Some(String::from("dashboard-token-0123456789"));
+
+        assert_eq!(
+            redact(
+                "GET /dashboard?token=dashboard-token-0123456789 by admin-token-0123456789",
+                &secrets(&config)
+            ),
+            format!("GET /dashboard?token={} by {}", REDACTED, REDACTED)
+        );
+    }
+
+    #[test]
+    fn json_lines_carry_the_command_fields() {
+        let record = Record::builder()
+            .level(log::Level::Info)
+            .target("m_bot")
+            .build();
+        let fields = Fields {
+            guild_id: Some(GuildId(1)),
+            user_id: UserId(2),
+            command: String::from("vsay"),
+        };
+
+        let line = json_line("2020-01-01T00:00:00Z", &record, "hi", Some(&fields));
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "timestamp": "2020-01-01T00:00:00Z",
+                "level": "INFO",
+                "target": "m_bot",
+                "message": "hi",
+                "guild_id": "1",
+                "user_id": "2",
+                "command": "vsay",
+            })
+        );
+
+        let line = json_line("2020-01-01T00:00:00Z", &record, "hi", None);
+        assert!(line.get("user_id").is_none());
+    }
+
+    #[test]
+    fn fields_are_per_thread() {
+        set_fields(Fields {
+            guild_id: None,
+            user_id: UserId(2),
+            command: String::from("ping"),
+        });
+        assert!(std::thread::spawn(|| fields().is_none()).join().unwrap());
+        assert_eq!(fields().unwrap().command, "ping");
+        clear_fields();
+        assert!(fields().is_none());
+    }
+}
@@ -2,15 +2,36 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
+mod activity;
+mod api;
+mod audio;
+mod autovoice;
 mod commands;
+mod config;
+mod context;
+mod countdown;
+mod dashboard;
+mod duration;
+mod logging;
+mod matching;
+mod metrics;
+mod playback;
+mod recording;
+mod reminders;
+mod server;
+mod settings;
+mod shutdown;
+mod soundboard;
+mod speech;
+mod store;
 mod tts;
 
 #[macro_use]
 extern crate log;
 extern crate chrono;
-extern crate env_logger;
 extern crate serenity;
 
+use serde::{Deserialize, Serialize};
 use serenity::{
     client::bridge::{gateway::ShardManager, voice::ClientVoiceManager},
     framework::standard::{
@@ -18,15 +39,21 @@ use serenity::{
         macros::{check, command, group, help},
         Args, CommandGroup, CommandResult, DispatchError, HelpOptions, StandardFramework,
     },
-    model::{channel::Message, event::ResumedEvent, gateway::Ready, id::GuildId, id::UserId},
+    http::Http,
+    model::{
+        channel::Message, event::ResumedEvent, gateway::Ready, id::GuildId, id::UserId,
+        voice::VoiceState,
+    },
     prelude::*,
     voice::AudioReceiver,
     Client, Result as SerenityResult,
 };
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 struct RollCallManager {
+    path: PathBuf,
     list: HashMap<GuildId, RollCall>,
 }
 
@@ -35,15 +62,31 @@ impl TypeMapKey for RollCallManager {
 }
 
 impl RollCallManager {
-    fn new() -> Self {
-        Self {
-            list: HashMap::new(),
+    /// Picks up the Roll Calls running when the bot last shut down from
+    /// `path`. The file is only written on shutdown, so it's removed once
+    /// read, or a crash later on would bring back stale Roll Calls.
+    fn resume(path: PathBuf) -> Self {
+        let list: HashMap<GuildId, RollCall> = store::load(&path);
+        if !list.is_empty() {
+            info!("Resuming {} Roll Calls from {:?}", list.len(), path);
+        }
+        if let Err(e) = std::fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Unable to remove {:?}: {}", path, e);
+            }
+        }
+
+        Self { path, list }
+    }
+
+    fn persist(&self) {
+        if let Err(why) = store::save(&self.path, &self.list) {
+            error!("Unable to save Roll Calls to {:?}: {}", self.path, why);
         }
     }
 
     fn start_roll_call_for(&mut self, guild_id: GuildId, call_by: UserId, requested: u16) -> bool {
-        let c = guild_id.clone();
-        if self.have_running_call_for(c) {
+        if self.have_running_call_for(guild_id) {
             false
         } else {
             self.list
@@ -54,10 +97,7 @@ impl RollCallManager {
 
     // returns true if roll call was found for this guild and removed successfully, false otherwise
     fn cancel_running_call_for(&mut self, guild_id: GuildId) -> bool {
-        match self.list.remove(&guild_id) {
-            Some(_) => true,
-            None => false,
-        }
+        self.list.remove(&guild_id).is_some()
     }
 
     fn have_running_call_for(&self, guild_id: GuildId) -> bool {
@@ -81,8 +121,14 @@ impl RollCallManager {
     fn get_roll_call_for(&self, guild_id: GuildId) -> Option<&RollCall> {
         self.list.get(&guild_id)
     }
+
+    /// Number of guilds with a Roll Call running.
+    fn active(&self) -> usize {
+        self.list.len()
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 struct RollCall {
     guild_id: GuildId,
     call_by: UserId,
@@ -93,9 +139,9 @@ struct RollCall {
 impl RollCall {
     fn new(guild_id: GuildId, call_by: UserId, requested: u16) -> Self {
         Self {
-            guild_id: guild_id,
-            call_by: call_by,
-            requested: requested,
+            guild_id,
+            call_by,
+            requested,
             joined: HashSet::<UserId>::new(),
         }
     }
@@ -106,7 +152,7 @@ impl RollCall {
 
     fn lack(&self) -> u16 {
         use std::convert::TryFrom;
-        let r = usize::try_from(self.requested).unwrap() - self.joined.len();
+        let r = usize::from(self.requested).saturating_sub(self.joined.len());
 
         u16::try_from(r).unwrap()
     }
@@ -135,18 +181,49 @@ impl TypeMapKey for VoiceManager {
     type Value = Arc<Mutex<ClientVoiceManager>>;
 }
 
-use commands::{ping::*, roll_call::*, say::*, shard::*, time::*, voice::*};
+use activity::VoiceActivity;
+use autovoice::AutoVoice;
+use commands::{
+    admin::*, config::*, listen::*, ping::*, queue::*, record::*, remind::*, roll_call::*, say::*,
+    shard::*, soundboard::*, time::*, voice::*,
+};
+use countdown::{AnnouncementPolicy, CountdownManager};
+use metrics::Metrics;
+use playback::PlaybackManager;
+use recording::{PacketOutcome, RecordingManager};
+use reminders::ReminderStore;
+use settings::SettingsStore;
+use soundboard::Soundboard;
+use speech::SpeechManager;
 
 group!({
     name: "general",
     options: {},
-    commands: [ping, say, time, latency],
+    commands: [ping, say, time, remind, latency, shards],
+});
+
+group!({
+    name: "Admin",
+    options: {
+        prefix: "admin",
+        description: "Run the bot, for its owners."
+    },
+    commands: [admin_guilds, admin_leave, admin_presence, admin_reload_config, admin_announce, admin_shutdown],
+});
+
+group!({
+    name: "Shard",
+    options: {
+        prefix: "shard",
+        description: "Manage the gateway shards, for the bot's owners."
+    },
+    commands: [shard_restart],
 });
 
 group!({
     name: "Voice",
     options: {},
-    commands: [join, leave, mute, unmute, deafen, undeafen, vtime, vsay],
+    commands: [join, leave, mute, unmute, deafen, undeafen, vtime, vsay, voicestats, listen, queue, skip, pause, resume, stop, volume],
 });
 
 group!({
@@ -158,6 +235,49 @@ group!({
     commands: [start, ready, cancel, status],
 });
 
+group!({
+    name: "Recording",
+    options: {
+        prefix: "record",
+        description: "Records the voice channel the bot is in, per user and mixed down.",
+    },
+    commands: [record_start, record_stop],
+});
+
+group!({
+    name: "Config",
+    options: {
+        prefix: "config",
+        description: "Settings of this server, for administrators."
+    },
+    commands: [config_get, config_set, config_reset],
+});
+
+group!({
+    name: "Soundboard",
+    options: {
+        prefix: "sb",
+        description: "Play short sound clips in the voice channel."
+    },
+    commands: [sb_list, sb_play, sb_add],
+});
+
+/// Every group of commands, in the order `help` lists them.
+///
+/// The `#[group]` macro generates `static` instances of the options set for the group.
+/// They're made in the pattern: `#name_GROUP` for the group instance and `#name_GROUP_OPTIONS`.
+/// #name is turned all uppercase
+pub static GROUPS: &[&CommandGroup] = &[
+    &GENERAL_GROUP,
+    &VOICE_GROUP,
+    &RALLY_GROUP,
+    &RECORDING_GROUP,
+    &SOUNDBOARD_GROUP,
+    &CONFIG_GROUP,
+    &SHARD_GROUP,
+    &ADMIN_GROUP,
+];
+
 #[help]
 #[max_levenshtein_distance(3)]
 #[indention_prefix = "+"]
@@ -184,39 +304,137 @@ impl EventHandler for Handler {
     // private channels, and more.
     //
     // In this case, just print what the current user's username is.
-    fn ready(&self, _: Context, ready: Ready) {
+    fn ready(&self, ctx: Context, ready: Ready) {
         debug!("{} is connected!", ready.user.name);
+        rejoin_voice(&ctx);
     }
 
-    fn resume(&self, _: Context, resume: ResumedEvent) {
+    fn resume(&self, ctx: Context, resume: ResumedEvent) {
         // Log at the DEBUG level.
         //
         // In this example, this will not show up in the logs because DEBUG is
         // below INFO, which is the set debug level.
         debug!("Resumed; trace: {:?}", resume.trace);
+        rejoin_voice(&ctx);
+    }
+
+    fn voice_state_update(
+        &self,
+        ctx: Context,
+        guild_id: Option<GuildId>,
+        _old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        let guild_id = match guild_id {
+            Some(guild_id) => guild_id,
+            None => return,
+        };
+
+        let auto_lock = match context::state::<AutoVoice>(&ctx.data) {
+            Ok(auto_lock) => auto_lock,
+            Err(_) => return,
+        };
+
+        // moved or disconnected by someone else, keep track of where to
+        // rejoin, if anywhere
+        if new.user_id == ctx.cache.read().user.id {
+            match new.channel_id {
+                Some(channel_id) => auto_lock.lock().joined(guild_id, channel_id),
+                None => auto_lock.lock().left(guild_id),
+            }
+
+            return;
+        }
+
+        let channel_id = match new.channel_id {
+            Some(channel_id) => channel_id,
+            None => return,
+        };
+
+        let roles = match ctx.cache.read().member(guild_id, new.user_id) {
+            Some(member) => member.roles,
+            None => Vec::new(),
+        };
+
+        let follow = {
+            let auto = auto_lock.lock();
+            auto.follows(new.user_id, &roles) && auto.channel(guild_id) != Some(channel_id)
+        };
+        if follow {
+            info!(
+                "[{}] Following {} into {}",
+                guild_id, new.user_id, channel_id
+            );
+            if let Err(why) = commands::voice::connect(&ctx.data, &ctx.http, guild_id, channel_id) {
+                warn!(
+                    "[{}] Unable to follow {} into {}: {}",
+                    guild_id, new.user_id, channel_id, why
+                );
+            }
+        }
+    }
+}
+
+/// Joins the voice channels the bot was in again, as connections don't
+/// survive the gateway reconnecting.
+fn rejoin_voice(ctx: &Context) {
+    let channels = match context::state::<AutoVoice>(&ctx.data) {
+        Ok(auto) => auto.lock().channels(),
+        Err(_) => return,
+    };
+
+    for (guild_id, channel_id) in channels {
+        info!("[{}] Rejoining {}", guild_id, channel_id);
+        if let Err(why) = commands::voice::connect(&ctx.data, &ctx.http, guild_id, channel_id) {
+            warn!("[{}] Unable to rejoin {}: {}", guild_id, channel_id, why);
+        }
     }
 }
 
 // Audio Receiver
-struct Receiver;
+pub struct Receiver {
+    guild_id: GuildId,
+    activity: Arc<Mutex<VoiceActivity>>,
+    recordings: Arc<Mutex<RecordingManager>>,
+    speech: Arc<Mutex<SpeechManager>>,
+    http: Arc<Http>,
+}
+
 impl Receiver {
-    pub fn new() -> Self {
-        // You can manage state here, such as a buffer of audio packet bytes so
-        // you can later store them in intervals.
-        Self {}
+    pub fn new(
+        guild_id: GuildId,
+        data: &RwLock<ShareMap>,
+        http: Arc<Http>,
+    ) -> context::BotResult<Self> {
+        Ok(Self {
+            guild_id,
+            activity: context::state::<VoiceActivity>(data)?,
+            recordings: context::state::<RecordingManager>(data)?,
+            speech: context::state::<SpeechManager>(data)?,
+            http,
+        })
+    }
+
+    fn map_ssrc(&self, ssrc: u32, user_id: u64) {
+        self.activity
+            .lock()
+            .map_ssrc(self.guild_id, ssrc, UserId(user_id));
+        self.recordings
+            .lock()
+            .map_ssrc(self.guild_id, ssrc, UserId(user_id));
     }
 }
 
 impl AudioReceiver for Receiver {
-    fn speaking_update(&mut self, _ssrc: u32, _user_id: u64, _speaking: bool) {
-        // You can implement logic here so that you can differentiate users'
-        // SSRCs and map the SSRC to the User ID and maintain a state in
-        // `Receiver`. Using this map, you can map the `ssrc` in `voice_packet`
-        // to the user ID and handle their audio packets separately.
-        info!(
-            "[AUDIO SPEAKING UPDATE] SSRC: {}, UserID: {}, Speeking: {}",
-            _ssrc, _user_id, _speaking
+    fn speaking_update(&mut self, ssrc: u32, user_id: u64, speaking: bool) {
+        trace!(
+            "[{}] Speaking update, SSRC: {}, UserID: {}, Speaking: {}",
+            self.guild_id,
+            ssrc,
+            user_id,
+            speaking
         );
+        self.map_ssrc(ssrc, user_id);
     }
 
     fn voice_packet(
@@ -224,46 +442,88 @@ impl AudioReceiver for Receiver {
         ssrc: u32,
         sequence: u16,
         _timestamp: u32,
-        _stereo: bool,
+        stereo: bool,
         data: &[i16],
         compressed_size: usize,
     ) {
-        info!("Audio packet's first 5 bytes: {:?}", data.get(..5));
-        info!(
-            "Audio packet sequence {:05} has {:04} bytes (decompressed from {}), SSRC {}",
+        trace!(
+            "[{}] Audio packet sequence {:05} has {:04} samples (decompressed from {}), SSRC {}",
+            self.guild_id,
             sequence,
             data.len(),
             compressed_size,
             ssrc,
         );
+        let user_id = {
+            let mut activity = self.activity.lock();
+            activity.packet(self.guild_id, ssrc);
+
+            activity.user(self.guild_id, ssrc)
+        };
+
+        // commands are run as the speaker, unknown ones can't be heard
+        if let Some(user_id) = user_id {
+            self.speech
+                .lock()
+                .packet(self.guild_id, user_id, stereo, data);
+        }
+
+        let mut recordings = self.recordings.lock();
+        if recordings.packet(self.guild_id, ssrc, sequence, stereo, data)
+            == PacketOutcome::LimitReached
+        {
+            if let Some(recording) = recordings.stop(self.guild_id) {
+                warn!("[{}] Recording size limit reached", self.guild_id);
+                recording::spawn_finish(Arc::clone(&self.http), recording, "size limit reached");
+            }
+        }
     }
 
-    fn client_connect(&mut self, _ssrc: u32, _user_id: u64) {
-        // You can implement your own logic here to handle a user who has joined the
-        // voice channel e.g., allocate structures, map their SSRC to User ID.
-        info!(
-            "[AUDIO CLIENT CONNECT] SSRC: {}, UserID: {}",
-            _ssrc, _user_id
+    fn client_connect(&mut self, ssrc: u32, user_id: u64) {
+        debug!(
+            "[{}] Client connect, SSRC: {}, UserID: {}",
+            self.guild_id, ssrc, user_id
         );
+        self.map_ssrc(ssrc, user_id);
     }
 
-    fn client_disconnect(&mut self, _user_id: u64) {
-        // You can implement your own logic here to handle a user who has left the
-        // voice channel e.g., finalise processing of statistics etc.
-        // You will typically need to map the User ID to their SSRC; observed when
-        // speaking or connecting.
-        info!("[AUDIO CLIENT DISCONNECT] UserID: {}", _user_id);
+    fn client_disconnect(&mut self, user_id: u64) {
+        debug!("[{}] Client disconnect, UserID: {}", self.guild_id, user_id);
+        self.activity
+            .lock()
+            .disconnect(self.guild_id, UserId(user_id));
+        self.speech
+            .lock()
+            .disconnect(self.guild_id, UserId(user_id));
     }
 }
 
 fn main() {
-    std::env::set_var("RUST_LOG", "m_bot=trace,serenity=trace");
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  - {}", error);
+            }
+
+            std::process::exit(1);
+        }
+    };
+
     std::env::set_var("RUST_BACKTRACE", "1");
-    env_logger::init();
+    logging::init(&config.bot.log_level, config.bot.log_format == "json");
     info!("Hello, world!");
 
-    // bot token
-    let token = std::env::var("BOT_TOKEN").expect("BOT_TOKEN ENV VAR NOT FOUND");
+    let token = config.bot.token.clone();
+    let prefix = config.bot.prefix.clone();
+    let bind = config.http.bind.clone();
+    let sharding = config::Sharding::parse(&config.bot.shards).expect("Validated sharding");
+    let storage = config.storage.clone();
+    let countdown = config.countdown.clone();
+    let voice = config.voice.clone();
+    let speech = config.speech.clone();
+    config::set(config);
 
     // Create a new instance of the Client, logging in as a bot. This will
     // automatically prepend your bot token with "Bot ", which is a requirement
@@ -274,7 +534,53 @@ fn main() {
         let mut data = client.data.write();
         data.insert::<ShardManagerContainer>(Arc::clone(&client.shard_manager));
         data.insert::<VoiceManager>(Arc::clone(&client.voice_manager));
-        data.insert::<RollCallManager>(Arc::new(Mutex::new(RollCallManager::new())));
+        data.insert::<RollCallManager>(Arc::new(Mutex::new(RollCallManager::resume(
+            storage.roll_calls_file(),
+        ))));
+        data.insert::<Soundboard>(Arc::new(Mutex::new(Soundboard::new(storage.sounds_dir()))));
+        data.insert::<Metrics>(Arc::new(Mutex::new(Metrics::new())));
+        data.insert::<SettingsStore>(Arc::new(Mutex::new(SettingsStore::new(
+            storage.settings_file(),
+        ))));
+        data.insert::<VoiceActivity>(Arc::new(Mutex::new(VoiceActivity::new())));
+        data.insert::<RecordingManager>(Arc::new(Mutex::new(RecordingManager::new(
+            storage.recordings_dir(),
+        ))));
+        data.insert::<CountdownManager>(Arc::new(Mutex::new(CountdownManager::new(
+            AnnouncementPolicy::from_config(&countdown),
+        ))));
+
+        let playback = Arc::new(Mutex::new(PlaybackManager::new(Arc::clone(
+            &client.voice_manager,
+        ))));
+        PlaybackManager::spawn_driver(Arc::clone(&playback));
+        data.insert::<PlaybackManager>(playback);
+
+        let reminders = Arc::new(Mutex::new(ReminderStore::new(storage.reminders_file())));
+        ReminderStore::spawn_scheduler(
+            Arc::clone(&reminders),
+            Arc::clone(&client.data),
+            Arc::clone(&client.cache_and_http.http),
+        );
+        data.insert::<ReminderStore>(reminders);
+
+        let auto = Arc::new(Mutex::new(AutoVoice::from_config(&voice)));
+        AutoVoice::spawn_idle_check(
+            Arc::clone(&auto),
+            Arc::clone(&client.data),
+            Arc::clone(&client.cache_and_http.cache),
+            Arc::clone(&client.cache_and_http.http),
+        );
+        data.insert::<AutoVoice>(auto);
+
+        let (speech, heard) = SpeechManager::from_config(&speech);
+        speech::spawn_dispatcher(
+            heard,
+            Arc::clone(&client.data),
+            Arc::clone(&client.cache_and_http.cache),
+            Arc::clone(&client.cache_and_http.http),
+        );
+        data.insert::<SpeechManager>(Arc::new(Mutex::new(speech)));
     }
 
     // We will fetch your bot's owners and id
@@ -288,93 +594,172 @@ fn main() {
         Err(why) => panic!("Could not access application info: {:?}", why),
     };
 
-    client.with_framework(
-        // Configures the client, allowing for options to mutate how the
-        // framework functions.
-        //
-        // Refer to the documentation for
-        // `serenity::ext::framework::Configuration` for all available
-        // configurations.
-        StandardFramework::new()
-            .configure(|c| {
-                c.with_whitespace(true)
-                    .on_mention(Some(bot_id))
-                    .prefix(".")
-                    .case_insensitivity(true)
-                    // Sets the bot's owners. These will be used for commands that
-                    // are owners only.
-                    .owners(owners)
-            })
-            // Set a function that's called whenever an attempted command-call's
-            // command could not be found.
-            .unrecognised_command(|_, _, unknown_command_name| {
-                println!("Could not find command named '{}'", unknown_command_name);
-            })
-            // Set a function that's called whenever a message is not a command.
-            .normal_message(|ctx, msg| {
-                let guild_lock = match msg.guild(&ctx.cache) {
-                    Some(g) => g,
-                    None => return (),
-                };
-
-                let user_id = msg.author.id;
-                let user_name = &msg.author.name;
-
-                let guild = guild_lock.read();
-                let guild_id = guild.id;
-                let guild_name = &guild.name;
-
-                let message = format!(
-                    "Message ({} - {}) ({} - {}): {}",
-                    guild_id, guild_name, user_id, user_name, msg.content
+    // Configures the client, allowing for options to mutate how the
+    // framework functions.
+    //
+    // Refer to the documentation for
+    // `serenity::ext::framework::Configuration` for all available
+    // configurations.
+    let framework = StandardFramework::new()
+        .configure(|c| {
+            c.with_whitespace(true)
+                .on_mention(Some(bot_id))
+                // The guild's own prefix, replacing the default one.
+                .dynamic_prefix(move |ctx, msg| {
+                    let guild_prefix = msg.guild_id.and_then(|guild_id| {
+                        context::state::<SettingsStore>(&ctx.data)
+                            .ok()?
+                            .lock()
+                            .get(guild_id)
+                            .prefix
+                    });
+
+                    Some(guild_prefix.unwrap_or_else(|| prefix.clone()))
+                })
+                .case_insensitivity(true)
+                // Sets the bot's owners. These will be used for commands that
+                // are owners only.
+                .owners(owners)
+        })
+        // Set a function that's called whenever an attempted command-call's
+        // command could not be found.
+        .unrecognised_command(|_, _, unknown_command_name| {
+            println!("Could not find command named '{}'", unknown_command_name);
+        })
+        // Set a function that's called whenever a message is not a command.
+        // Messages are only logged in the guilds that opted in with the
+        // `log_messages` setting.
+        .normal_message(|ctx, msg| {
+            let guild_id = match msg.guild_id {
+                Some(guild_id) => guild_id,
+                None => return,
+            };
+            if !settings::of(&ctx.data, guild_id).log_messages {
+                return;
+            }
+
+            let guild_name = msg
+                .guild(&ctx.cache)
+                .map(|guild| guild.read().name.clone())
+                .unwrap_or_default();
+            info!(
+                "Message ({} - {}) ({} - {}): {}",
+                guild_id, guild_name, msg.author.id, msg.author.name, msg.content
+            );
+        })
+        // Set a function that's called whenever a command's execution didn't complete for one
+        // reason or another. For example, when a user has exceeded a rate-limit or a command
+        // can only be performed by the bot owner.
+        .on_dispatch_error(|ctx, msg, error| match error {
+            DispatchError::Ratelimited(seconds) => {
+                let _ = msg
+                    .channel_id
+                    .say(&ctx.http, format!("Try this again in {} seconds.", seconds));
+            }
+            DispatchError::OnlyForGuilds => context::report(
+                &ctx.http,
+                msg.channel_id,
+                msg.author.id,
+                &context::BotError::GuildOnly.to_string(),
+            ),
+            _ => (),
+        })
+        // Set a function that's called before a command, refusing the ones
+        // the guild disabled.
+        .before(|ctx, msg, command_name| {
+            if shutdown::is_requested() {
+                return false;
+            }
+
+            logging::set_fields(logging::Fields {
+                guild_id: msg.guild_id,
+                user_id: msg.author.id,
+                command: command_name.to_string(),
+            });
+
+            let guild_id = match msg.guild_id {
+                Some(guild_id) => guild_id,
+                None => return true,
+            };
+            let settings = settings::of(&ctx.data, guild_id);
+            let prefix = settings
+                .prefix
+                .clone()
+                .unwrap_or_else(|| config::current().bot.prefix.clone());
+            let command = commands::invoked(GROUPS, &prefix, &msg.content, command_name);
+            let disabled = settings.is_disabled(&command);
+            if disabled {
+                context::report(
+                    &ctx.http,
+                    msg.channel_id,
+                    msg.author.id,
+                    "That command is disabled here.",
                 );
-                info!("{}", message);
-            })
-            // Set a function that's called whenever a command's execution didn't complete for one
-            // reason or another. For example, when a user has exceeded a rate-limit or a command
-            // can only be performed by the bot owner.
-            .on_dispatch_error(|ctx, msg, error| {
-                if let DispatchError::Ratelimited(seconds) = error {
-                    let _ = msg.channel_id.say(
-                        &ctx.http,
-                        &format!("Try this again in {} seconds.", seconds),
-                    );
-                }
-            })
-            // The `#[group]` macro generates `static` instances of the options set for the group.
-            // They're made in the pattern: `#name_GROUP` for the group instance and `#name_GROUP_OPTIONS`.
-            // #name is turned all uppercase
-            .group(&GENERAL_GROUP)
-            .group(&VOICE_GROUP)
-            .group(&RALLY_GROUP)
-            .help(&MY_HELP),
-    );
+                logging::clear_fields();
+            }
+
+            !disabled
+        })
+        // Set a function that's called after a command, replying with the
+        // error of commands that failed.
+        .after(|ctx, msg, command_name, result| {
+            if let Ok(metrics) = context::state::<Metrics>(&ctx.data) {
+                metrics.lock().command(command_name, result.is_ok());
+            }
+            if let Err(why) = result {
+                debug!("Command '{}' failed: {}", command_name, why.0);
+                context::report(&ctx.http, msg.channel_id, msg.author.id, &why.0);
+            }
+            logging::clear_fields();
+        });
+
+    let framework = GROUPS
+        .iter()
+        .fold(framework, |framework, group| framework.group(group));
+    client.with_framework(framework.help(&MY_HELP));
 
     // lets create the http server for azure does not kill our server.
-    let server = tiny_http::Server::http("0.0.0.0:80").unwrap();
-    std::thread::spawn(move || {
-        info!("Starting tiny http server!");
-        loop {
-            // blocks until the next request is received
-            let request = match server.recv() {
-                Ok(rq) => rq,
-                Err(e) => {
-                    println!("error: {}", e);
-                    break;
-                }
-            };
-
-            let _ = request.respond(tiny_http::Response::from_string("Hello, m-bot running!"));
+    let server = match tiny_http::Server::http(&bind) {
+        Ok(server) => server,
+        Err(why) => {
+            error!("Unable to start the http server on {}: {}", bind, why);
+            std::process::exit(1);
         }
-    });
+    };
+    if let Err(why) = shutdown::install(
+        Arc::clone(&client.data),
+        Arc::clone(&client.cache_and_http.http),
+    ) {
+        error!("Unable to handle shutdown signals: {}", why);
+    }
+
+    let http_server = server::spawn(
+        server,
+        Arc::clone(&client.data),
+        Arc::clone(&client.cache_and_http.cache),
+        Arc::clone(&client.cache_and_http.http),
+    );
 
-    // Finally, start a single shard, and start listening to events.
+    // Finally, start the shards, and start listening to events.
     //
     // Shards will automatically attempt to reconnect, and will perform
     // exponential backoff until it reconnects.
-    if let Err(why) = client.start() {
+    info!("Starting shards: {:?}", sharding);
+    let started = match sharding {
+        config::Sharding::Auto => client.start_autosharded(),
+        config::Sharding::Total(total) => client.start_shards(total),
+        config::Sharding::Range { first, last, total } => {
+            client.start_shard_range([first, last], total)
+        }
+    };
+    if let Err(why) = started {
         error!("Client error: {:?}", why);
     }
+
+    if shutdown::is_requested() {
+        let _ = http_server.join();
+        info!("Bye!");
+    }
 }
 
 /// Sending a message can fail, due to a network error, an
@@ -0,0 +1,124 @@
+//! Fuzzy matching of names typed by users, like channel names.
+
+#[derive(Debug, PartialEq)]
+pub enum Match<T> {
+    Found(T),
+    /// Names of the candidates matching equally well.
+    Ambiguous(Vec<String>),
+    NotFound,
+}
+
+/// Edits needed to turn `a` into `b`.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// How well `name` matches `query`, lower is better: exact matches first,
+/// then names starting with the query, containing it, and a few typos away.
+fn score(query: &str, name: &str) -> Option<(u8, usize)> {
+    if name == query {
+        return Some((0, 0));
+    }
+
+    if name.starts_with(query) {
+        return Some((1, name.len() - query.len()));
+    }
+
+    if name.contains(query) {
+        return Some((2, name.len() - query.len()));
+    }
+
+    let distance = levenshtein(query, name);
+    if distance <= (query.chars().count() / 4).max(1) {
+        return Some((3, distance));
+    }
+
+    None
+}
+
+/// Finds the candidate closest to `query`, ignoring case.
+pub fn best_match<T: Clone>(query: &str, candidates: &[(T, String)]) -> Match<T> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Match::NotFound;
+    }
+
+    let scored: Vec<_> = candidates
+        .iter()
+        .filter_map(|c| score(&query, &c.1.to_lowercase()).map(|s| (s, c)))
+        .collect();
+
+    let best = match scored.iter().map(|(score, _)| *score).min() {
+        Some(best) => best,
+        None => return Match::NotFound,
+    };
+
+    let mut matches: Vec<&(T, String)> = scored
+        .into_iter()
+        .filter(|(score, _)| *score == best)
+        .map(|(_, c)| c)
+        .collect();
+
+    if matches.len() == 1 {
+        Match::Found(matches.remove(0).0.clone())
+    } else {
+        Match::Ambiguous(matches.iter().map(|c| c.1.clone()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels() -> Vec<(u64, String)> {
+        vec![
+            (1, String::from("General")),
+            (2, String::from("Raid 1")),
+            (3, String::from("Raid 2")),
+            (4, String::from("AFK")),
+            (5, String::from("general-chat")),
+        ]
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("general", "general"), 0);
+        assert_eq!(levenshtein("genral", "general"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn prefers_closer_matches() {
+        let channels = channels();
+        assert_eq!(best_match("general", &channels), Match::Found(1));
+        assert_eq!(best_match("  GENERAL ", &channels), Match::Found(1));
+        assert_eq!(best_match("gen", &channels), Match::Found(1));
+        assert_eq!(best_match("chat", &channels), Match::Found(5));
+        assert_eq!(best_match("afj", &channels), Match::Found(4));
+        assert_eq!(best_match("genral", &channels), Match::Found(1));
+    }
+
+    #[test]
+    fn reports_ambiguous_and_missing_names() {
+        let channels = channels();
+        assert_eq!(
+            best_match("raid", &channels),
+            Match::Ambiguous(vec![String::from("Raid 1"), String::from("Raid 2")])
+        );
+        assert_eq!(best_match("lobby", &channels), Match::NotFound);
+        assert_eq!(best_match("", &channels), Match::NotFound);
+    }
+}
@@ -0,0 +1,191 @@
+//! Counters exposed by the `/metrics` endpoint, in the Prometheus text
+//! format.
+//!
+//! Commands are counted by the framework's `after` hook, everything else is
+//! read from the shared state when scraped.
+
+use serenity::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Metrics {
+    commands: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl TypeMapKey for Metrics {
+    type Value = Arc<Mutex<Metrics>>;
+}
+
+/// A shard as seen when scraping.
+pub struct ShardGauge {
+    pub id: u64,
+    pub connected: bool,
+    pub latency: Option<Duration>,
+}
+
+/// What's read from the shared state when scraping.
+pub struct Gauges {
+    pub shards: Vec<ShardGauge>,
+    pub roll_calls: usize,
+    pub voice_connections: usize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a command that ran, and whether it failed.
+    pub fn command(&mut self, name: &str, ok: bool) {
+        *self.commands.entry(name.to_string()).or_insert(0) += 1;
+        if !ok {
+            *self.errors.entry(name.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "m_bot_commands_total", "counter", "Commands run.");
+        for (name, count) in &self.commands {
+            let _ = writeln!(
+                out,
+                "m_bot_commands_total{{command=\"{}\"}} {}",
+                escape(name),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "m_bot_command_errors_total",
+            "counter",
+            "Commands that failed.",
+        );
+        for (name, count) in &self.errors {
+            let _ = writeln!(
+                out,
+                "m_bot_command_errors_total{{command=\"{}\"}} {}",
+                escape(name),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "m_bot_shard_connected",
+            "gauge",
+            "Whether a shard is connected.",
+        );
+        for shard in &gauges.shards {
+            let _ = writeln!(
+                out,
+                "m_bot_shard_connected{{shard=\"{}\"}} {}",
+                shard.id, shard.connected as u8
+            );
+        }
+
+        header(
+            &mut out,
+            "m_bot_shard_latency_seconds",
+            "gauge",
+            "Heartbeat latency of a shard.",
+        );
+        for shard in &gauges.shards {
+            if let Some(latency) = shard.latency {
+                let _ = writeln!(
+                    out,
+                    "m_bot_shard_latency_seconds{{shard=\"{}\"}} {}",
+                    shard.id,
+                    latency.as_secs_f64()
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "m_bot_roll_calls_active",
+            "gauge",
+            "Roll Calls running.",
+        );
+        let _ = writeln!(out, "m_bot_roll_calls_active {}", gauges.roll_calls);
+
+        header(
+            &mut out,
+            "m_bot_voice_connections",
+            "gauge",
+            "Voice channels the bot is in.",
+        );
+        let _ = writeln!(out, "m_bot_voice_connections {}", gauges.voice_connections);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let mut metrics = Metrics::new();
+        metrics.command("vsay", true);
+        metrics.command("vsay", false);
+        metrics.command("ping", true);
+
+        let text = metrics.render(&Gauges {
+            shards: vec![
+                ShardGauge {
+                    id: 0,
+                    connected: true,
+                    latency: Some(Duration::from_millis(250)),
+                },
+                ShardGauge {
+                    id: 1,
+                    connected: false,
+                    latency: None,
+                },
+            ],
+            roll_calls: 2,
+            voice_connections: 1,
+        });
+
+        let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            lines,
+            [
+                "m_bot_commands_total{command=\"ping\"} 1",
+                "m_bot_commands_total{command=\"vsay\"} 2",
+                "m_bot_command_errors_total{command=\"vsay\"} 1",
+                "m_bot_shard_connected{shard=\"0\"} 1",
+                "m_bot_shard_connected{shard=\"1\"} 0",
+                "m_bot_shard_latency_seconds{shard=\"0\"} 0.25",
+                "m_bot_roll_calls_active 2",
+                "m_bot_voice_connections 1",
+            ]
+        );
+        assert!(text.contains("# TYPE m_bot_commands_total counter\n"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,364 @@
+//! Per guild audio queues feeding the voice handlers.
+//!
+//! Everything the bot says or plays in voice is enqueued here instead of
+//! being handed to `Handler::play` directly, so sounds no longer talk over
+//! each other. Tracks are split in priority lanes: a track of a higher lane
+//! preempts (pauses) the one playing, which resumes once the higher lanes
+//! are drained.
+
+use serenity::client::bridge::voice::ClientVoiceManager;
+use serenity::model::id::GuildId;
+use serenity::prelude::*;
+use serenity::voice::{pcm, AudioSource, LockedAudio};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const DEFAULT_VOLUME: f32 = 1.0;
+pub const MAX_VOLUME: f32 = 2.0;
+
+/// How often the driver checks for finished tracks, one opus frame.
+const TICK: Duration = Duration::from_millis(20);
+
+/// Lanes of a queue, from lowest to highest priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// TTS from `vsay`, soundboard clips.
+    Chatter,
+    /// Bot announcements, like reminders.
+    Announcement,
+    /// Countdown ticks, these must play on time.
+    Countdown,
+}
+
+impl Priority {
+    const LANES: usize = 3;
+
+    fn lane(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Chatter => "chatter",
+            Priority::Announcement => "announcement",
+            Priority::Countdown => "countdown",
+        }
+    }
+}
+
+pub struct Track {
+    pub title: String,
+    pub priority: Priority,
+    source: Box<dyn AudioSource>,
+}
+
+impl Track {
+    pub fn new<T: Into<String>>(
+        title: T,
+        priority: Priority,
+        source: Box<dyn AudioSource>,
+    ) -> Self {
+        Self {
+            title: title.into(),
+            priority,
+            source,
+        }
+    }
+}
+
+struct Playing {
+    title: String,
+    priority: Priority,
+    audio: LockedAudio,
+}
+
+impl Playing {
+    fn finished(&self) -> bool {
+        self.audio.lock().finished
+    }
+
+    /// Makes the mixer drop the audio on its next frame.
+    fn discard(&self) {
+        let mut audio = self.audio.lock();
+        audio.source = pcm(true, std::io::empty());
+        audio.play();
+    }
+}
+
+struct GuildQueue {
+    lanes: [VecDeque<Track>; Priority::LANES],
+    current: Option<Playing>,
+    /// Tracks preempted by a higher lane, resumed last in first out.
+    suspended: Vec<Playing>,
+    paused: bool,
+    volume: f32,
+}
+
+impl GuildQueue {
+    fn new() -> Self {
+        Self {
+            lanes: Default::default(),
+            current: None,
+            suspended: Vec::new(),
+            paused: false,
+            volume: DEFAULT_VOLUME,
+        }
+    }
+
+    fn next_priority(&self) -> Option<Priority> {
+        [
+            Priority::Countdown,
+            Priority::Announcement,
+            Priority::Chatter,
+        ]
+        .iter()
+        .cloned()
+        .find(|p| !self.lanes[p.lane()].is_empty())
+    }
+
+    fn clear(&mut self) -> usize {
+        let mut cleared = self.lanes.iter().map(VecDeque::len).sum();
+        for lane in self.lanes.iter_mut() {
+            lane.clear();
+        }
+
+        for playing in self
+            .current
+            .take()
+            .into_iter()
+            .chain(self.suspended.drain(..))
+        {
+            playing.discard();
+            cleared += 1;
+        }
+        self.paused = false;
+
+        cleared
+    }
+}
+
+/// A snapshot of a guild queue, for displaying it.
+pub struct QueueStatus {
+    pub now_playing: Option<(String, Priority)>,
+    pub suspended: Vec<(String, Priority)>,
+    pub upcoming: Vec<(String, Priority)>,
+    pub paused: bool,
+    pub volume: f32,
+}
+
+pub struct PlaybackManager {
+    voice: Arc<Mutex<ClientVoiceManager>>,
+    queues: HashMap<GuildId, GuildQueue>,
+}
+
+impl TypeMapKey for PlaybackManager {
+    type Value = Arc<Mutex<PlaybackManager>>;
+}
+
+impl PlaybackManager {
+    /// The voice manager is locked by the playback manager while holding its
+    /// own lock, never lock them in the opposite order.
+    pub fn new(voice: Arc<Mutex<ClientVoiceManager>>) -> Self {
+        Self {
+            voice,
+            queues: HashMap::new(),
+        }
+    }
+
+    /// Starts a thread advancing every queue as tracks finish.
+    pub fn spawn_driver(manager: Arc<Mutex<PlaybackManager>>) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            manager.lock().tick();
+            std::thread::sleep(TICK);
+        })
+    }
+
+    /// Adds a track to the guild queue, starting it right away if nothing of
+    /// the same or higher priority is playing.
+    ///
+    /// Returns how many tracks are ahead of it.
+    pub fn enqueue(&mut self, guild_id: GuildId, track: Track) -> Result<usize, &'static str> {
+        if self.voice.lock().get(guild_id).is_none() {
+            return Err("Not in a voice channel");
+        }
+
+        let queue = self.queues.entry(guild_id).or_insert_with(GuildQueue::new);
+        let ahead = queue.lanes[track.priority.lane()..]
+            .iter()
+            .map(VecDeque::len)
+            .sum::<usize>()
+            + queue
+                .current
+                .iter()
+                .filter(|p| p.priority >= track.priority)
+                .count();
+        queue.lanes[track.priority.lane()].push_back(track);
+
+        self.advance(guild_id);
+
+        Ok(ahead)
+    }
+
+    /// Skips the playing track, returning its title.
+    pub fn skip(&mut self, guild_id: GuildId) -> Option<String> {
+        let skipped = self.queues.get_mut(&guild_id)?.current.take()?;
+        skipped.discard();
+        self.advance(guild_id);
+
+        Some(skipped.title)
+    }
+
+    pub fn pause(&mut self, guild_id: GuildId) -> bool {
+        match self.queues.get_mut(&guild_id) {
+            Some(queue) if !queue.paused => {
+                queue.paused = true;
+                if let Some(playing) = &queue.current {
+                    playing.audio.lock().pause();
+                }
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn resume(&mut self, guild_id: GuildId) -> bool {
+        let resumed = match self.queues.get_mut(&guild_id) {
+            Some(queue) if queue.paused => {
+                queue.paused = false;
+                if let Some(playing) = &queue.current {
+                    playing.audio.lock().play();
+                }
+
+                true
+            }
+            _ => false,
+        };
+        self.advance(guild_id);
+
+        resumed
+    }
+
+    /// Stops playback and empties the queue, returning how many tracks were dropped.
+    pub fn stop(&mut self, guild_id: GuildId) -> usize {
+        self.queues.get_mut(&guild_id).map_or(0, GuildQueue::clear)
+    }
+
+    /// Sets the volume for the playing and upcoming tracks, clamped to `MAX_VOLUME`.
+    pub fn set_volume(&mut self, guild_id: GuildId, volume: f32) -> f32 {
+        let volume = volume.clamp(0.0, MAX_VOLUME);
+        let queue = self.queues.entry(guild_id).or_insert_with(GuildQueue::new);
+        queue.volume = volume;
+        for playing in queue.current.iter().chain(queue.suspended.iter()) {
+            playing.audio.lock().volume(volume);
+        }
+
+        volume
+    }
+
+    pub fn status(&self, guild_id: GuildId) -> QueueStatus {
+        let describe = |p: &Playing| (p.title.clone(), p.priority);
+        match self.queues.get(&guild_id) {
+            Some(queue) => QueueStatus {
+                now_playing: queue.current.as_ref().map(describe),
+                suspended: queue.suspended.iter().rev().map(describe).collect(),
+                upcoming: queue
+                    .lanes
+                    .iter()
+                    .rev()
+                    .flatten()
+                    .map(|t| (t.title.clone(), t.priority))
+                    .collect(),
+                paused: queue.paused,
+                volume: queue.volume,
+            },
+            None => QueueStatus {
+                now_playing: None,
+                suspended: Vec::new(),
+                upcoming: Vec::new(),
+                paused: false,
+                volume: DEFAULT_VOLUME,
+            },
+        }
+    }
+
+    /// Advances every queue, see `advance`.
+    pub fn tick(&mut self) {
+        let guilds: Vec<GuildId> = self.queues.keys().cloned().collect();
+        for guild_id in guilds {
+            self.advance(guild_id);
+        }
+    }
+
+    /// Drops the finished track, preempts it for a higher priority one, or
+    /// starts the next track, resuming suspended ones before lower lanes.
+    fn advance(&mut self, guild_id: GuildId) {
+        let queue = match self.queues.get_mut(&guild_id) {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        if queue.paused {
+            return;
+        }
+
+        if queue.current.as_ref().is_some_and(Playing::finished) {
+            queue.current = None;
+        }
+        queue.suspended.retain(|p| !p.finished());
+
+        let next = queue.next_priority();
+        if let Some(current) = queue.current.take() {
+            match next {
+                Some(priority) if priority > current.priority => {
+                    debug!(
+                        "[{}] '{}' preempted by {}",
+                        guild_id,
+                        current.title,
+                        priority.name()
+                    );
+                    current.audio.lock().pause();
+                    queue.suspended.push(current);
+                }
+                _ => {
+                    queue.current = Some(current);
+
+                    return;
+                }
+            }
+        }
+
+        let suspended = queue.suspended.last().map(|p| p.priority);
+        if suspended.is_some() && suspended >= next {
+            let playing = queue.suspended.pop().unwrap();
+            playing.audio.lock().play();
+            queue.current = Some(playing);
+
+            return;
+        }
+
+        let track = match next.and_then(|p| queue.lanes[p.lane()].pop_front()) {
+            Some(track) => track,
+            None => return,
+        };
+
+        let mut voice = self.voice.lock();
+        match voice.get_mut(guild_id) {
+            Some(handler) => {
+                let audio = handler.play_returning(track.source);
+                audio.lock().volume(queue.volume);
+                queue.current = Some(Playing {
+                    title: track.title,
+                    priority: track.priority,
+                    audio,
+                });
+            }
+            None => {
+                debug!("[{}] Left voice, dropping queue", guild_id);
+                queue.clear();
+            }
+        }
+    }
+}
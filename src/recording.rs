@@ -0,0 +1,502 @@
+//! Recording of voice channels to WAV files.
+//!
+//! Each SSRC gets its own track, written as packets come in, and a mixdown
+//! of all of them is made once the recording stops. Tracks are aligned to
+//! the recording start: the time a user is silent (no packets are sent) and
+//! packets lost in transit (gaps in the sequence numbers) are written as
+//! silence, so every file can be played back in sync.
+
+use crate as bot;
+use crate::activity::FRAME;
+use crate::audio::{CHANNELS, SAMPLE_RATE};
+use crate::duration;
+use crate::store;
+
+use chrono::Utc;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Most a recording can write across its tracks before it's stopped, 256MB.
+pub const MAX_RECORDING_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Samples of a frame, per channel.
+const FRAME_SAMPLES: usize = 960;
+
+/// Samples of a frame, all channels.
+const FRAME_LEN: usize = FRAME_SAMPLES * CHANNELS as usize;
+
+/// Bytes of a frame in a track.
+const FRAME_BYTES: u64 = FRAME_LEN as u64 * 2;
+
+/// Biggest hole in the sequence numbers filled with silence, a second. Bigger
+/// ones are treated as the user having stopped talking.
+const MAX_SEQUENCE_GAP: u16 = 50;
+
+/// How far behind the clock a track can be before it's padded with silence,
+/// absorbs the jitter of packets arriving in bursts.
+const MAX_DRIFT_FRAMES: u64 = 10;
+
+fn spec() -> WavSpec {
+    WavSpec {
+        channels: CHANNELS,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    }
+}
+
+/// The file of a single SSRC.
+struct Track<W: Write + Seek> {
+    writer: WavWriter<W>,
+    /// Frames written so far, the position of the track in frames.
+    frames: u64,
+    last_sequence: Option<u16>,
+}
+
+impl<W: Write + Seek> Track<W> {
+    fn new(writer: W) -> hound::Result<Self> {
+        Ok(Self {
+            writer: WavWriter::new(writer, spec())?,
+            frames: 0,
+            last_sequence: None,
+        })
+    }
+
+    fn silence(&mut self, frames: u64) -> hound::Result<()> {
+        for _ in 0..frames * FRAME_LEN as u64 {
+            self.writer.write_sample(0i16)?;
+        }
+        self.frames += frames;
+
+        Ok(())
+    }
+
+    /// Writes a packet received at `clock` frames since the recording start,
+    /// returning how many frames were written, silence included. At most
+    /// `max_frames` are written, the packet is dropped if the silence before
+    /// it takes them all.
+    fn push(
+        &mut self,
+        clock: u64,
+        sequence: u16,
+        stereo: bool,
+        data: &[i16],
+        max_frames: u64,
+    ) -> hound::Result<u64> {
+        let start = self.frames;
+        match self.last_sequence.map(|last| sequence.wrapping_sub(last)) {
+            // Late or duplicated, it can't be written back in time
+            Some(0) => return Ok(0),
+            Some(ahead) if ahead > u16::MAX / 2 => return Ok(0),
+            Some(ahead) if ahead <= MAX_SEQUENCE_GAP => {
+                self.silence(u64::from(ahead - 1).min(max_frames))?
+            }
+            _ => (),
+        }
+
+        if clock > self.frames + MAX_DRIFT_FRAMES {
+            let left = max_frames - (self.frames - start);
+            self.silence((clock - self.frames).min(left))?;
+        }
+
+        if self.frames - start >= max_frames {
+            return Ok(self.frames - start);
+        }
+
+        if stereo {
+            for sample in data {
+                self.writer.write_sample(*sample)?;
+            }
+        } else {
+            for sample in data {
+                self.writer.write_sample(*sample)?;
+                self.writer.write_sample(*sample)?;
+            }
+        }
+        self.frames += 1;
+        self.last_sequence = Some(sequence);
+
+        Ok(self.frames - start)
+    }
+}
+
+/// Files written by a finished recording.
+pub struct RecordingSummary {
+    pub dir: PathBuf,
+    pub tracks: Vec<PathBuf>,
+    pub mixed: Option<PathBuf>,
+    pub seconds: u64,
+}
+
+pub struct Recording {
+    pub channel_id: ChannelId,
+    pub started_by: UserId,
+    /// Text channel the recording was started from, for announcements.
+    pub text_channel_id: ChannelId,
+    dir: PathBuf,
+    started: Instant,
+    tracks: HashMap<u32, Track<BufWriter<File>>>,
+    users: HashMap<u32, UserId>,
+    bytes: u64,
+}
+
+impl Recording {
+    fn clock(&self) -> u64 {
+        (self.started.elapsed().as_millis() / FRAME.as_millis()) as u64
+    }
+
+    fn track_path(&self, ssrc: u32) -> PathBuf {
+        self.dir.join(format!("ssrc-{}.wav", ssrc))
+    }
+
+    /// Finalizes the tracks, names them after their users and mixes them
+    /// down into `mixed.wav`.
+    pub fn finish(self) -> RecordingSummary {
+        let seconds = self.started.elapsed().as_secs();
+        let mut tracks = Vec::new();
+        for (ssrc, track) in self.tracks {
+            let path = self.dir.join(format!("ssrc-{}.wav", ssrc));
+            if let Err(e) = track.writer.finalize() {
+                error!("Unable to finalize {:?}: {}", path, e);
+
+                continue;
+            }
+
+            let named = match self.users.get(&ssrc) {
+                Some(user_id) => self.dir.join(format!("{}-{}.wav", user_id, ssrc)),
+                None => path.clone(),
+            };
+
+            match std::fs::rename(&path, &named) {
+                Ok(()) => tracks.push(named),
+                Err(_) => tracks.push(path),
+            }
+        }
+        tracks.sort();
+
+        let mixed = self.dir.join("mixed.wav");
+        let mixed = match mix(&tracks, &mixed) {
+            Ok(()) if !tracks.is_empty() => Some(mixed),
+            Ok(()) => None,
+            Err(e) => {
+                error!("Unable to mix down {:?}: {}", self.dir, e);
+
+                None
+            }
+        };
+
+        RecordingSummary {
+            dir: self.dir,
+            tracks,
+            mixed,
+            seconds,
+        }
+    }
+}
+
+/// Sums the tracks into `output`, clipping what doesn't fit in 16 bits.
+/// Tracks are aligned to their start, shorter ones end in silence.
+pub fn mix<P: AsRef<Path>>(tracks: &[P], output: &Path) -> hound::Result<()> {
+    let mut readers = Vec::new();
+    for track in tracks {
+        readers.push(WavReader::open(track)?.into_samples::<i16>());
+    }
+
+    let mut writer = WavWriter::create(output, spec())?;
+    loop {
+        let mut sum = 0i32;
+        let mut done = true;
+        for samples in readers.iter_mut() {
+            if let Some(sample) = samples.next() {
+                sum += i32::from(sample?);
+                done = false;
+            }
+        }
+
+        if done {
+            break;
+        }
+
+        writer.write_sample(sum.clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16)?;
+    }
+
+    writer.finalize()
+}
+
+/// Finishes a recording in the background, mixdown included, posting where
+/// it was saved to the channel it was started from.
+pub fn spawn_finish(http: Arc<Http>, recording: Recording, reason: &'static str) {
+    std::thread::spawn(move || {
+        let text_channel_id = recording.text_channel_id;
+        let summary = recording.finish();
+        info!(
+            "Recording saved to {:?}, {} track(s), {}s",
+            summary.dir,
+            summary.tracks.len(),
+            summary.seconds
+        );
+
+        bot::check_sending_message(text_channel_id.say(
+            &http,
+            format!(
+                "Recording stopped ({}): {} track(s) of {}, saved to `{}`",
+                reason,
+                summary.tracks.len(),
+                duration::format(Duration::from_secs(summary.seconds)),
+                summary.dir.display()
+            ),
+        ));
+    });
+}
+
+/// What became of a packet handed to the manager.
+#[derive(Debug, PartialEq)]
+pub enum PacketOutcome {
+    Ignored,
+    Written,
+    /// The recording hit `MAX_RECORDING_BYTES`, it should be stopped.
+    LimitReached,
+}
+
+pub struct RecordingManager {
+    dir: PathBuf,
+    recordings: HashMap<GuildId, Recording>,
+}
+
+impl TypeMapKey for RecordingManager {
+    type Value = Arc<Mutex<RecordingManager>>;
+}
+
+impl RecordingManager {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            recordings: HashMap::new(),
+        }
+    }
+
+    pub fn start(
+        &mut self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        started_by: UserId,
+        text_channel_id: ChannelId,
+    ) -> Result<(), &'static str> {
+        if self.recordings.contains_key(&guild_id) {
+            return Err("Already recording");
+        }
+
+        let dir = self
+            .dir
+            .join(guild_id.to_string())
+            .join(Utc::now().format("%Y%m%d-%H%M%S").to_string());
+        if std::fs::create_dir_all(&dir).is_err() {
+            return Err("Unable to create the recordings folder.");
+        }
+
+        self.recordings.insert(
+            guild_id,
+            Recording {
+                channel_id,
+                started_by,
+                text_channel_id,
+                dir,
+                started: Instant::now(),
+                tracks: HashMap::new(),
+                users: HashMap::new(),
+                bytes: 0,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self, guild_id: GuildId) -> Option<Recording> {
+        self.recordings.remove(&guild_id)
+    }
+
+    pub fn recording(&self, guild_id: GuildId) -> Option<&Recording> {
+        self.recordings.get(&guild_id)
+    }
+
+    pub fn map_ssrc(&mut self, guild_id: GuildId, ssrc: u32, user_id: UserId) {
+        if let Some(recording) = self.recordings.get_mut(&guild_id) {
+            recording.users.insert(ssrc, user_id);
+        }
+    }
+
+    pub fn packet(
+        &mut self,
+        guild_id: GuildId,
+        ssrc: u32,
+        sequence: u16,
+        stereo: bool,
+        data: &[i16],
+    ) -> PacketOutcome {
+        let recording = match self.recordings.get_mut(&guild_id) {
+            Some(recording) => recording,
+            None => return PacketOutcome::Ignored,
+        };
+
+        let max_frames = MAX_RECORDING_BYTES.saturating_sub(recording.bytes) / FRAME_BYTES;
+        if max_frames == 0 {
+            return PacketOutcome::LimitReached;
+        }
+
+        let clock = recording.clock();
+        if !recording.tracks.contains_key(&ssrc) {
+            let path = recording.track_path(ssrc);
+            let track = File::create(&path)
+                .map_err(hound::Error::from)
+                .and_then(|file| Track::new(BufWriter::new(file)));
+            match track {
+                Ok(track) => {
+                    recording.tracks.insert(ssrc, track);
+                }
+                Err(e) => {
+                    error!("Unable to create {:?}: {}", path, e);
+
+                    return PacketOutcome::Ignored;
+                }
+            }
+        }
+
+        let track = recording.tracks.get_mut(&ssrc).unwrap();
+        match track.push(clock, sequence, stereo, data, max_frames) {
+            Ok(frames) => {
+                recording.bytes += frames * FRAME_BYTES;
+                if frames == max_frames {
+                    PacketOutcome::LimitReached
+                } else {
+                    PacketOutcome::Written
+                }
+            }
+            Err(e) => {
+                error!("[{}] Unable to record SSRC {}: {}", guild_id, ssrc, e);
+
+                PacketOutcome::Ignored
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn frame(value: i16) -> Vec<i16> {
+        vec![value; FRAME_LEN]
+    }
+
+    /// Runs `record` on a track written in memory, returning its samples.
+    fn samples<F: FnOnce(&mut Track<&mut Cursor<Vec<u8>>>)>(record: F) -> Vec<i16> {
+        let mut cursor = Cursor::new(Vec::new());
+        {
+            let mut track = Track::new(&mut cursor).unwrap();
+            record(&mut track);
+            track.writer.finalize().unwrap();
+        }
+        cursor.set_position(0);
+
+        WavReader::new(cursor)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn fills_sequence_gaps_with_silence() {
+        let written = samples(|track| {
+            assert_eq!(track.push(0, 10, true, &frame(1), u64::MAX).unwrap(), 1);
+            assert_eq!(track.push(1, 11, true, &frame(2), u64::MAX).unwrap(), 1);
+            // 12 and 13 were lost
+            assert_eq!(track.push(3, 14, true, &frame(3), u64::MAX).unwrap(), 3);
+            // late and duplicated packets are dropped
+            assert_eq!(track.push(4, 12, true, &frame(9), u64::MAX).unwrap(), 0);
+            assert_eq!(track.push(4, 14, true, &frame(9), u64::MAX).unwrap(), 0);
+        });
+
+        let frames: Vec<i16> = written.chunks(FRAME_LEN).map(|f| f[0]).collect();
+        assert_eq!(frames, vec![1, 2, 0, 0, 3]);
+    }
+
+    #[test]
+    fn pads_silence_to_stay_aligned_with_the_clock() {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut track = Track::new(&mut cursor).unwrap();
+        // first packet a second after the recording started
+        assert_eq!(
+            track.push(50, 65000, true, &frame(1), u64::MAX).unwrap(),
+            51
+        );
+        // talked again after a long pause, sequence numbers wrapped around
+        assert_eq!(
+            track.push(200, 100, true, &frame(2), u64::MAX).unwrap(),
+            150
+        );
+        // jitter within the allowed drift isn't padded
+        assert_eq!(track.push(205, 101, true, &frame(3), u64::MAX).unwrap(), 1);
+
+        assert_eq!(track.frames, 202);
+    }
+
+    #[test]
+    fn caps_the_silence_to_the_frames_left() {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut track = Track::new(&mut cursor).unwrap();
+        // first talked half an hour in, with room for 100 frames
+        assert_eq!(track.push(90_000, 1, true, &frame(1), 100).unwrap(), 100);
+        assert_eq!(track.frames, 100);
+        assert_eq!(track.push(90_001, 2, true, &frame(1), 5).unwrap(), 5);
+        assert_eq!(track.push(90_002, 3, true, &frame(1), 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn duplicates_mono_packets_to_both_channels() {
+        let written = samples(|track| {
+            track.push(0, 1, false, &[1, 2, 3], u64::MAX).unwrap();
+        });
+
+        assert_eq!(written, vec![1, 1, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn mixes_tracks_with_clipping() {
+        let dir = std::env::temp_dir().join(format!("m-bot-mix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, data: &[i16]| {
+            let path = dir.join(name);
+            let mut writer = WavWriter::create(&path, spec()).unwrap();
+            for sample in data {
+                writer.write_sample(*sample).unwrap();
+            }
+            writer.finalize().unwrap();
+
+            path
+        };
+
+        let a = write("a.wav", &[1000, -1000, 30000, 5]);
+        let b = write("b.wav", &[500, 500, 30000, 5, 7, 7]);
+        let mixed = dir.join("mixed.wav");
+        mix(&[a, b], &mixed).unwrap();
+
+        let samples: Vec<i16> = WavReader::open(&mixed)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(samples, vec![1500, -500, i16::MAX, 10, 7, 7]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,366 @@
+//! Reminders set with `.remind`, persisted so they survive restarts.
+//!
+//! A scheduler thread checks for due reminders every second and posts them,
+//! also speaking them in voice when asked to. Reminders that came due while
+//! the bot was offline fire as soon as it's back.
+
+use crate as bot;
+use crate::context;
+use crate::playback::{PlaybackManager, Priority, Track};
+use crate::settings;
+use crate::store;
+use crate::tts::TextToSpeech;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::prelude::*;
+use serenity::voice::pcm;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How many reminders a single user can have pending.
+pub const MAX_PENDING_PER_USER: usize = 25;
+
+/// Longest text a reminder can hold.
+pub const MAX_TEXT_LEN: usize = 500;
+
+/// How often the scheduler looks for due reminders.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Who a reminder is for.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Target {
+    /// Mentions the author in the channel it was set in.
+    Author,
+    /// Posts to a channel, without mentioning anyone.
+    Channel,
+    /// Like `Author`, also spoken in the guild's voice channel.
+    Voice,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reminder {
+    pub id: u64,
+    pub author: UserId,
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    pub target: Target,
+    pub due: DateTime<Utc>,
+    pub text: String,
+}
+
+impl Reminder {
+    fn message(&self) -> String {
+        match self.target {
+            Target::Author | Target::Voice => {
+                format!("{}, reminder: {}", self.author.mention(), self.text)
+            }
+            Target::Channel => format!("Reminder: {}", self.text),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Reminders {
+    next_id: u64,
+    pending: BTreeMap<u64, Reminder>,
+}
+
+pub struct ReminderStore {
+    path: PathBuf,
+    state: Reminders,
+}
+
+impl TypeMapKey for ReminderStore {
+    type Value = Arc<Mutex<ReminderStore>>;
+}
+
+impl ReminderStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let state: Reminders = store::load(&path);
+        info!(
+            "Loaded {} pending reminders from {:?}",
+            state.pending.len(),
+            path
+        );
+
+        Self { path, state }
+    }
+
+    /// Registers a reminder, returning its id.
+    pub fn add(
+        &mut self,
+        author: UserId,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        target: Target,
+        due: DateTime<Utc>,
+        text: &str,
+    ) -> Result<u64, &'static str> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err("What should I remind about?");
+        }
+
+        if text.chars().count() > MAX_TEXT_LEN {
+            return Err("Reminders can be up to 500 characters long.");
+        }
+
+        let pending = self
+            .state
+            .pending
+            .values()
+            .filter(|r| r.author == author)
+            .count();
+        if pending >= MAX_PENDING_PER_USER {
+            return Err("You have too many pending reminders, delete some first.");
+        }
+
+        self.state.next_id += 1;
+        let id = self.state.next_id;
+        self.state.pending.insert(
+            id,
+            Reminder {
+                id,
+                author,
+                guild_id,
+                channel_id,
+                target,
+                due,
+                text: text.to_string(),
+            },
+        );
+        self.persist();
+
+        Ok(id)
+    }
+
+    /// The reminders of a guild, or the author's own ones outside of guilds,
+    /// soonest first.
+    pub fn list(&self, guild_id: Option<GuildId>, author: UserId) -> Vec<&Reminder> {
+        let mut reminders: Vec<&Reminder> = self
+            .state
+            .pending
+            .values()
+            .filter(|r| r.guild_id == guild_id && (guild_id.is_some() || r.author == author))
+            .collect();
+        reminders.sort_by_key(|r| r.due);
+
+        reminders
+    }
+
+    /// Deletes a reminder, only its author can.
+    pub fn remove(
+        &mut self,
+        id: u64,
+        guild_id: Option<GuildId>,
+        author: UserId,
+    ) -> Result<Reminder, &'static str> {
+        match self.state.pending.get(&id) {
+            Some(r) if r.guild_id == guild_id && r.author == author => (),
+            Some(r) if r.guild_id == guild_id => {
+                return Err("Only the author of a reminder can delete it.")
+            }
+            _ => return Err("No reminder with that id."),
+        }
+
+        let reminder = self.state.pending.remove(&id).unwrap();
+        self.persist();
+
+        Ok(reminder)
+    }
+
+    /// Removes and returns the reminders due at `now`.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<Reminder> {
+        let due: Vec<u64> = self
+            .state
+            .pending
+            .values()
+            .filter(|r| r.due <= now)
+            .map(|r| r.id)
+            .collect();
+
+        if due.is_empty() {
+            return Vec::new();
+        }
+
+        let reminders = due
+            .iter()
+            .filter_map(|id| self.state.pending.remove(id))
+            .collect();
+        self.persist();
+
+        reminders
+    }
+
+    pub fn persist(&self) {
+        if let Err(why) = store::save(&self.path, &self.state) {
+            error!("Unable to save reminders to {:?}: {}", self.path, why);
+        }
+    }
+
+    /// Starts a thread posting reminders as they come due.
+    pub fn spawn_scheduler(
+        reminders: Arc<Mutex<ReminderStore>>,
+        data: Arc<RwLock<ShareMap>>,
+        http: Arc<Http>,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            let due = reminders.lock().take_due(Utc::now());
+            for reminder in due {
+                fire(&data, &http, &reminder);
+            }
+
+            std::thread::sleep(TICK);
+        })
+    }
+}
+
+fn fire(data: &Arc<RwLock<ShareMap>>, http: &Http, reminder: &Reminder) {
+    debug!("Firing reminder #{}", reminder.id);
+    bot::check_sending_message(reminder.channel_id.say(http, reminder.message()));
+
+    let guild_id = match (reminder.target, reminder.guild_id) {
+        (Target::Voice, Some(guild_id)) => guild_id,
+        _ => return,
+    };
+
+    // Fetching the speech takes a round trip to the provider, which would
+    // hold up the other reminders due.
+    let data = Arc::clone(data);
+    let (id, text) = (reminder.id, reminder.text.clone());
+    std::thread::spawn(move || speak(&data, guild_id, id, &text));
+}
+
+fn speak(data: &RwLock<ShareMap>, guild_id: GuildId, id: u64, text: &str) {
+    let speech = match settings::of(data, guild_id)
+        .voicerss()
+        .get_speech(&format!("Reminder: {}", text))
+    {
+        Ok(speech) => speech,
+        Err(why) => {
+            warn!("Unable to speak reminder #{}: {}", id, why);
+
+            return;
+        }
+    };
+
+    let manager_lock = match context::state::<PlaybackManager>(data) {
+        Ok(manager_lock) => manager_lock,
+        Err(_) => return,
+    };
+
+    let track = Track::new(
+        format!("reminder #{}", id),
+        Priority::Announcement,
+        pcm(true, speech),
+    );
+    let result = manager_lock.lock().enqueue(guild_id, track);
+    if let Err(why) = result {
+        warn!("Unable to speak reminder #{}: {}", id, why);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn store(name: &str) -> ReminderStore {
+        let path = std::env::temp_dir().join(format!(
+            "m-bot-reminders-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        ReminderStore::new(path)
+    }
+
+    fn add(store: &mut ReminderStore, author: u64, minutes: i64) -> u64 {
+        store
+            .add(
+                UserId(author),
+                Some(GuildId(1)),
+                ChannelId(2),
+                Target::Author,
+                Utc::now() + Duration::minutes(minutes),
+                "check the raid",
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn lists_soonest_first() {
+        let mut store = store("list");
+        let later = add(&mut store, 7, 30);
+        let sooner = add(&mut store, 8, 5);
+
+        let ids: Vec<u64> = store
+            .list(Some(GuildId(1)), UserId(7))
+            .iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, vec![sooner, later]);
+        assert!(store.list(Some(GuildId(9)), UserId(7)).is_empty());
+        assert!(store.list(None, UserId(7)).is_empty());
+    }
+
+    #[test]
+    fn only_the_author_deletes() {
+        let mut store = store("remove");
+        let id = add(&mut store, 7, 30);
+
+        assert!(store.remove(id, Some(GuildId(1)), UserId(8)).is_err());
+        assert!(store.remove(id, Some(GuildId(9)), UserId(7)).is_err());
+        assert_eq!(
+            store.remove(id, Some(GuildId(1)), UserId(7)).unwrap().id,
+            id
+        );
+        assert!(store.remove(id, Some(GuildId(1)), UserId(7)).is_err());
+    }
+
+    #[test]
+    fn validates_reminders() {
+        let mut store = store("validate");
+        let due = Utc::now();
+        let add = |store: &mut ReminderStore, text: &str| {
+            store.add(UserId(7), None, ChannelId(2), Target::Author, due, text)
+        };
+
+        assert!(add(&mut store, "  ").is_err());
+        assert!(add(&mut store, &"a".repeat(MAX_TEXT_LEN + 1)).is_err());
+        for _ in 0..MAX_PENDING_PER_USER {
+            assert!(add(&mut store, "stretch").is_ok());
+        }
+        assert!(add(&mut store, "stretch").is_err());
+    }
+
+    #[test]
+    fn takes_due_reminders_and_persists() {
+        let mut store = store("due");
+        let past = add(&mut store, 7, -1);
+        let future = add(&mut store, 7, 30);
+
+        let due: Vec<u64> = store.take_due(Utc::now()).iter().map(|r| r.id).collect();
+        assert_eq!(due, vec![past]);
+        assert!(store.take_due(Utc::now()).is_empty());
+
+        let reloaded = ReminderStore::new(store.path.clone());
+        let ids: Vec<u64> = reloaded
+            .list(Some(GuildId(1)), UserId(7))
+            .iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, vec![future]);
+        assert_eq!(reloaded.state.next_id, 2);
+
+        let _ = std::fs::remove_file(&store.path);
+    }
+}
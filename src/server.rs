@@ -0,0 +1,201 @@
+//! The HTTP server for health checks, metrics and the admin API.
+//!
+//! - `/healthz` answers as long as the process runs.
+//! - `/readyz` answers 200 once every shard is connected, 503 until then.
+//! - `/metrics` has the `metrics` module's counters.
+//! - `/dashboard` is the `dashboard` module's roll call page, behind its own
+//!   token.
+//! - `/api` is the `api` module's.
+//!
+//! It stops once a shutdown is requested.
+
+use crate::api::{self, Api};
+use crate::config;
+use crate::context;
+use crate::dashboard;
+use crate::metrics::{Gauges, Metrics, ShardGauge};
+use crate::shutdown;
+use crate::{RollCallManager, ShardManagerContainer, VoiceManager};
+
+use serenity::cache::Cache;
+use serenity::client::bridge::voice::ClientVoiceManager;
+use serenity::gateway::ConnectionStage;
+use serenity::http::Http;
+use serenity::model::id::GuildId;
+use serenity::prelude::*;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tiny_http::{Header, Request, Response, Server};
+
+/// How often the server checks for a shutdown between requests.
+const POLL: Duration = Duration::from_millis(500);
+
+pub fn spawn(
+    server: Server,
+    data: Arc<RwLock<ShareMap>>,
+    cache: Arc<RwLock<Cache>>,
+    http: Arc<Http>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        info!("Starting tiny http server!");
+        let api = Api {
+            data: &data,
+            cache: &cache,
+            http: &http,
+        };
+        while !shutdown::is_requested() {
+            match server.recv_timeout(POLL) {
+                Ok(Some(request)) => respond(request, &api),
+                Ok(None) => (),
+                Err(why) => {
+                    error!("The http server stopped: {}", why);
+                    break;
+                }
+            }
+        }
+        info!("Stopped the http server");
+    })
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).expect("Valid header")
+}
+
+fn respond(mut request: Request, api: &Api) {
+    let data = api.data;
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    let response = match path.as_str() {
+        "/healthz" => Response::from_string("ok"),
+        "/readyz" => {
+            let shards = shards(data);
+            if is_ready(&shards) {
+                Response::from_string("ready")
+            } else {
+                Response::from_string("not ready").with_status_code(503)
+            }
+        }
+        "/metrics" => match context::state::<Metrics>(data) {
+            Ok(metrics) => {
+                let text = metrics.lock().render(&gauges(data, api.cache));
+                Response::from_string(text).with_header(content_type("text/plain; version=0.0.4"))
+            }
+            Err(_) => Response::from_string("metrics unavailable").with_status_code(500),
+        },
+        "/dashboard" => match config::current().http.dashboard_token.clone() {
+            None => Response::from_string("not found").with_status_code(404),
+            Some(token)
+                if !dashboard::is_authorized(
+                    request.url(),
+                    api::authorization(&request).as_deref(),
+                    &token,
+                ) =>
+            {
+                Response::from_string("unauthorized").with_status_code(401)
+            }
+            Some(_) => {
+                let html = dashboard::render(&dashboard::rows(data, api.cache));
+                Response::from_string(html).with_header(content_type("text/html; charset=utf-8"))
+            }
+        },
+        path if path == "/api" || path.starts_with("/api/") => {
+            let (status, body) = api.handle(&mut request);
+            Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(content_type("application/json"))
+        }
+        // Azure probes the root to know the bot is up.
+        "/" => Response::from_string("Hello, m-bot running!"),
+        _ => Response::from_string("not found").with_status_code(404),
+    };
+
+    if let Err(why) = request.respond(response) {
+        debug!("Unable to answer an http request: {}", why);
+    }
+}
+
+/// Ready once there are shards, all of them connected.
+fn is_ready(shards: &[ShardGauge]) -> bool {
+    !shards.is_empty() && shards.iter().all(|shard| shard.connected)
+}
+
+fn shards(data: &RwLock<ShareMap>) -> Vec<ShardGauge> {
+    let shard_manager = match context::state::<ShardManagerContainer>(data) {
+        Ok(shard_manager) => shard_manager,
+        Err(_) => return Vec::new(),
+    };
+    let manager = shard_manager.lock();
+    let runners = manager.runners.lock();
+
+    let mut shards: Vec<ShardGauge> = runners
+        .iter()
+        .map(|(id, runner)| ShardGauge {
+            id: id.0,
+            connected: runner.stage == ConnectionStage::Connected,
+            latency: runner.latency,
+        })
+        .collect();
+    shards.sort_by_key(|shard| shard.id);
+
+    shards
+}
+
+fn gauges(data: &RwLock<ShareMap>, cache: &RwLock<Cache>) -> Gauges {
+    let guilds: Vec<GuildId> = cache.read().guilds.keys().cloned().collect();
+
+    Gauges {
+        shards: shards(data),
+        roll_calls: context::state::<RollCallManager>(data)
+            .map(|manager| manager.lock().active())
+            .unwrap_or(0),
+        voice_connections: context::state::<VoiceManager>(data)
+            .map(|manager| voice_connections(&manager.lock(), &guilds))
+            .unwrap_or(0),
+    }
+}
+
+/// The guilds the voice manager has in a channel, however they got there.
+fn voice_connections(manager: &ClientVoiceManager, guilds: &[GuildId]) -> usize {
+    guilds
+        .iter()
+        .filter(|guild_id| {
+            manager
+                .get(**guild_id)
+                .is_some_and(|handler| handler.channel_id.is_some())
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::model::id::{ChannelId, UserId};
+
+    fn shard(id: u64, connected: bool) -> ShardGauge {
+        ShardGauge {
+            id,
+            connected,
+            latency: None,
+        }
+    }
+
+    #[test]
+    fn counts_the_voice_manager_connections() {
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut manager = ClientVoiceManager::new(1, UserId(1));
+        manager.set(0, sender);
+        manager.join(GuildId(1), ChannelId(10));
+        manager.join(GuildId(2), ChannelId(20));
+        manager.leave(GuildId(2));
+        let guilds = [GuildId(1), GuildId(2), GuildId(3)];
+
+        assert_eq!(voice_connections(&manager, &guilds), 1);
+    }
+
+    #[test]
+    fn ready_when_every_shard_is_connected() {
+        assert!(!is_ready(&[]));
+        assert!(!is_ready(&[shard(0, true), shard(1, false)]));
+        assert!(is_ready(&[shard(0, true), shard(1, true)]));
+    }
+}
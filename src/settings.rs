@@ -0,0 +1,346 @@
+//! Settings each guild can change with `config`, persisted so they survive
+//! restarts. Unset ones fall back to the bot's config.
+
+use crate::commands;
+use crate::store;
+use crate::tts::{AzureTextToSpeech, VoiceRSS};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId, RoleId};
+use serenity::prelude::*;
+use serenity::utils::{parse_channel, parse_role};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub const MAX_PREFIX_LEN: usize = 5;
+
+/// Commands that can't be disabled, or nobody could enable them again.
+const ALWAYS_ENABLED: &[&str] = &["config", "config get", "config set", "config reset", "help"];
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    pub prefix: Option<String>,
+    /// Language of the speech, like `en-us` or `pt-pt`.
+    pub language: Option<String>,
+    /// Voice of the speech, one the provider has for the language. Azure's
+    /// voices start with their language, like `en-US-GuyNeural`, the others
+    /// are VoiceRSS ones, like `Linda`.
+    pub voice: Option<String>,
+    /// Players a Roll Call asks for when `rc start` isn't given a number.
+    pub roll_call_players: Option<u16>,
+    /// Where Roll Call announcements go instead of the channel used.
+    pub announce_channel: Option<ChannelId>,
+    /// Role needed to start and cancel Roll Calls, besides administrators.
+    pub manager_role: Option<RoleId>,
+    /// Qualified names of commands, like `record start`, or the prefix of a
+    /// whole group, like `sb`.
+    pub disabled_commands: BTreeSet<String>,
+    /// Whether the content of messages is logged, off unless the guild opts in.
+    pub log_messages: bool,
+}
+
+/// The keys `config` knows, in the order it lists them.
+pub const KEYS: &[&str] = &[
+    "prefix",
+    "language",
+    "voice",
+    "roll_call_players",
+    "announce_channel",
+    "manager_role",
+    "disabled_commands",
+    "log_messages",
+];
+
+impl GuildSettings {
+    pub fn voicerss(&self) -> VoiceRSS {
+        VoiceRSS::with_voice(self.language.as_deref(), self.voice.as_deref())
+    }
+
+    pub fn azure(&self) -> AzureTextToSpeech {
+        AzureTextToSpeech::with_voice(self.language.as_deref(), self.voice.as_deref())
+    }
+
+    /// Whether the command with the qualified name `command`, or its group,
+    /// is disabled.
+    pub fn is_disabled(&self, command: &str) -> bool {
+        let command = command.to_lowercase();
+        let group = command.split_whitespace().next().unwrap_or_default();
+
+        self.disabled_commands.contains(&command) || self.disabled_commands.contains(group)
+    }
+
+    /// The value of a key, as shown to users.
+    pub fn get(&self, key: &str) -> Result<String, String> {
+        fn or_unset<T: ToString>(value: &Option<T>) -> String {
+            value
+                .as_ref()
+                .map(T::to_string)
+                .unwrap_or_else(|| String::from("not set"))
+        }
+
+        let value = match key {
+            "prefix" => or_unset(&self.prefix),
+            "language" => or_unset(&self.language),
+            "voice" => or_unset(&self.voice),
+            "roll_call_players" => or_unset(&self.roll_call_players),
+            "announce_channel" => or_unset(&self.announce_channel.map(|c| c.mention())),
+            "manager_role" => or_unset(&self.manager_role.map(|r| r.mention())),
+            "disabled_commands" if self.disabled_commands.is_empty() => String::from("none"),
+            "disabled_commands" => self
+                .disabled_commands
+                .iter()
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(", "),
+            "log_messages" if self.log_messages => String::from("on"),
+            "log_messages" => String::from("off"),
+            _ => return Err(unknown_key(key)),
+        };
+
+        Ok(value)
+    }
+
+    /// Parses and sets the value of a key.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(format!("What should `{}` be?", key));
+        }
+
+        match key {
+            "prefix" => {
+                if value.chars().count() > MAX_PREFIX_LEN || value.contains(char::is_whitespace) {
+                    return Err(format!(
+                        "Prefixes are up to {} characters, without spaces.",
+                        MAX_PREFIX_LEN
+                    ));
+                }
+                self.prefix = Some(value.to_string());
+            }
+            "language" => {
+                let valid = value.len() == 5
+                    && value.as_bytes()[2] == b'-'
+                    && value
+                        .split('-')
+                        .all(|part| part.chars().all(|c| c.is_ascii_alphabetic()));
+                if !valid {
+                    return Err(String::from("Languages look like `en-us` or `pt-pt`."));
+                }
+                self.language = Some(value.to_lowercase());
+            }
+            "voice" => {
+                if !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                    return Err(String::from(
+                        "Voices are names like `Linda` or `en-US-GuyNeural`.",
+                    ));
+                }
+                self.voice = Some(value.to_string());
+            }
+            "roll_call_players" => match value.parse::<u16>() {
+                Ok(players) if players > 0 => self.roll_call_players = Some(players),
+                _ => return Err(String::from("Use a number of players, like `10`.")),
+            },
+            "announce_channel" => match parse_channel(value) {
+                Some(channel_id) => self.announce_channel = Some(ChannelId(channel_id)),
+                None => return Err(String::from("Mention the channel, like #announcements.")),
+            },
+            "manager_role" => match parse_role(value) {
+                Some(role_id) => self.manager_role = Some(RoleId(role_id)),
+                None => return Err(String::from("Mention the role, like @Officers.")),
+            },
+            "disabled_commands" => {
+                let mut commands = BTreeSet::new();
+                for name in value.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                    let name = name.trim_start_matches('.').to_lowercase();
+                    let command = match name.as_str() {
+                        "help" => name,
+                        _ => commands::resolve(crate::GROUPS, &name).ok_or_else(|| {
+                            format!(
+                                "Unknown command `{}`, separate commands with commas, like `vsay, record start, sb`.",
+                                name
+                            )
+                        })?,
+                    };
+                    commands.insert(command);
+                }
+                if let Some(command) = commands
+                    .iter()
+                    .find(|c| ALWAYS_ENABLED.contains(&c.as_str()))
+                {
+                    return Err(format!("`{}` can't be disabled.", command));
+                }
+                self.disabled_commands = commands;
+            }
+            "log_messages" => match value.to_lowercase().as_str() {
+                "on" | "yes" | "true" => self.log_messages = true,
+                "off" | "no" | "false" => self.log_messages = false,
+                _ => return Err(String::from("Use `on` or `off`.")),
+            },
+            _ => return Err(unknown_key(key)),
+        }
+
+        Ok(())
+    }
+
+    /// Sets a key back to its default.
+    pub fn reset(&mut self, key: &str) -> Result<(), String> {
+        match key {
+            "prefix" => self.prefix = None,
+            "language" => self.language = None,
+            "voice" => self.voice = None,
+            "roll_call_players" => self.roll_call_players = None,
+            "announce_channel" => self.announce_channel = None,
+            "manager_role" => self.manager_role = None,
+            "disabled_commands" => self.disabled_commands.clear(),
+            "log_messages" => self.log_messages = false,
+            _ => return Err(unknown_key(key)),
+        }
+
+        Ok(())
+    }
+}
+
+fn unknown_key(key: &str) -> String {
+    format!("Unknown setting `{}`, use one of: {}", key, KEYS.join(", "))
+}
+
+pub struct SettingsStore {
+    path: PathBuf,
+    guilds: HashMap<GuildId, GuildSettings>,
+}
+
+impl TypeMapKey for SettingsStore {
+    type Value = Arc<Mutex<SettingsStore>>;
+}
+
+impl SettingsStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let guilds: HashMap<GuildId, GuildSettings> = store::load(&path);
+        info!(
+            "Loaded the settings of {} guilds from {:?}",
+            guilds.len(),
+            path
+        );
+
+        Self { path, guilds }
+    }
+
+    pub fn get(&self, guild_id: GuildId) -> GuildSettings {
+        self.guilds.get(&guild_id).cloned().unwrap_or_default()
+    }
+
+    /// Changes the settings of a guild, saving them if `change` succeeds.
+    pub fn update<F>(&mut self, guild_id: GuildId, change: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut GuildSettings) -> Result<(), String>,
+    {
+        let mut settings = self.get(guild_id);
+        change(&mut settings)?;
+
+        if settings == GuildSettings::default() {
+            self.guilds.remove(&guild_id);
+        } else {
+            self.guilds.insert(guild_id, settings);
+        }
+
+        store::save(&self.path, &self.guilds).map_err(String::from)
+    }
+
+    pub fn persist(&self) {
+        if let Err(why) = store::save(&self.path, &self.guilds) {
+            error!("Unable to save settings to {:?}: {}", self.path, why);
+        }
+    }
+}
+
+/// The settings of a guild, the defaults if the store is missing.
+pub fn of(data: &RwLock<ShareMap>, guild_id: GuildId) -> GuildSettings {
+    crate::context::state::<SettingsStore>(data)
+        .map(|store| store.lock().get(guild_id))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_and_resets_keys() {
+        let mut settings = GuildSettings::default();
+        settings.set("prefix", "!").unwrap();
+        settings.set("language", "PT-PT").unwrap();
+        settings.set("roll_call_players", "10").unwrap();
+        settings.set("announce_channel", "<#42>").unwrap();
+        settings.set("manager_role", "<@&7>").unwrap();
+        settings
+            .set(
+                "disabled_commands",
+                ".vsay, time,  record   START, RC start, sb",
+            )
+            .unwrap();
+        settings.set("log_messages", "ON").unwrap();
+
+        assert_eq!(settings.prefix.as_deref(), Some("!"));
+        assert_eq!(settings.get("language").unwrap(), "pt-pt");
+        assert_eq!(settings.roll_call_players, Some(10));
+        assert_eq!(settings.get("announce_channel").unwrap(), "<#42>");
+        assert_eq!(settings.manager_role, Some(RoleId(7)));
+        assert_eq!(
+            settings.get("disabled_commands").unwrap(),
+            "rc start, record start, sb, time, vsay"
+        );
+        assert!(settings.is_disabled("VSAY"));
+        assert!(settings.is_disabled("record start"));
+        assert!(!settings.is_disabled("record stop"));
+        assert!(settings.is_disabled("sb play"));
+        assert_eq!(settings.get("log_messages").unwrap(), "on");
+
+        for key in KEYS {
+            settings.reset(key).unwrap();
+        }
+        assert_eq!(settings, GuildSettings::default());
+        assert_eq!(settings.get("voice").unwrap(), "not set");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let mut settings = GuildSettings::default();
+        assert!(settings.set("prefix", "too long").is_err());
+        assert!(settings.set("prefix", "").is_err());
+        assert!(settings.set("language", "english").is_err());
+        assert!(settings.set("voice", "<@1>").is_err());
+        assert!(settings.set("roll_call_players", "0").is_err());
+        assert!(settings.set("announce_channel", "general").is_err());
+        assert!(settings.set("manager_role", "<#42>").is_err());
+        assert!(settings.set("disabled_commands", "time, config").is_err());
+        assert!(settings.set("disabled_commands", "config set").is_err());
+        assert!(settings.set("disabled_commands", "help").is_err());
+        assert!(settings.set("disabled_commands", "time sb").is_err());
+        assert!(settings.set("disabled_commands", "start").is_err());
+        assert!(settings.set("log_messages", "maybe").is_err());
+        assert!(settings.set("colour", "blue").is_err());
+        assert!(settings.get("colour").is_err());
+        assert_eq!(settings, GuildSettings::default());
+    }
+
+    #[test]
+    fn persists_changes() {
+        let path = std::env::temp_dir().join(format!("m-bot-settings-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = SettingsStore::new(path.clone());
+        store.update(GuildId(1), |s| s.set("prefix", "!")).unwrap();
+        assert!(store
+            .update(GuildId(1), |s| s.set("prefix", "way too long"))
+            .is_err());
+
+        let reloaded = SettingsStore::new(path.clone());
+        assert_eq!(reloaded.get(GuildId(1)).prefix.as_deref(), Some("!"));
+        assert_eq!(reloaded.get(GuildId(2)), GuildSettings::default());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
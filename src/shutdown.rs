@@ -0,0 +1,89 @@
+//! Graceful shutdown on SIGINT and SIGTERM, or `admin shutdown`, so a
+//! redeploy doesn't cut the bot off mid-state.
+//!
+//! From the first signal on commands are refused, then countdowns are
+//! cancelled, voice channels left, state saved and the shards shut down,
+//! which makes `Client::start` return. The HTTP server stops on its own once
+//! it sees `is_requested`. A second signal exits right away.
+
+use crate::autovoice::AutoVoice;
+use crate::commands::voice;
+use crate::context;
+use crate::countdown::CountdownManager;
+use crate::reminders::ReminderStore;
+use crate::settings::SettingsStore;
+use crate::{RollCallManager, ShardManagerContainer};
+
+use serenity::http::Http;
+use serenity::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Time for the voice channel leaves to reach Discord before the shards
+/// that send them go down.
+const LEAVE_GRACE: Duration = Duration::from_secs(1);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+pub fn install(data: Arc<RwLock<ShareMap>>, http: Arc<Http>) -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(move || {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            warn!("Shutting down now.");
+            std::process::exit(1);
+        }
+
+        info!("Shutting down, signal again to force it.");
+        shut_down(&data, &http);
+    })
+}
+
+/// Shuts the bot down like a signal would, unless it already is.
+pub fn request(data: &RwLock<ShareMap>, http: &Arc<Http>) -> bool {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+
+    info!("Shutting down.");
+    shut_down(data, http);
+
+    true
+}
+
+fn shut_down(data: &RwLock<ShareMap>, http: &Arc<Http>) {
+    if let Ok(countdowns) = context::state::<CountdownManager>(data) {
+        let cancelled = countdowns.lock().cancel_all();
+        info!("Cancelled {} countdowns", cancelled);
+    }
+
+    let channels = context::state::<AutoVoice>(data)
+        .map(|auto| auto.lock().channels())
+        .unwrap_or_default();
+    for (guild_id, _) in &channels {
+        if let Err(why) = voice::disconnect(data, http, *guild_id) {
+            warn!("[{}] Unable to leave voice: {}", guild_id, why);
+        }
+    }
+    if !channels.is_empty() {
+        info!("Left {} voice channels", channels.len());
+        std::thread::sleep(LEAVE_GRACE);
+    }
+
+    if let Ok(roll_calls) = context::state::<RollCallManager>(data) {
+        roll_calls.lock().persist();
+    }
+    if let Ok(settings) = context::state::<SettingsStore>(data) {
+        settings.lock().persist();
+    }
+    if let Ok(reminders) = context::state::<ReminderStore>(data) {
+        reminders.lock().persist();
+    }
+
+    if let Ok(shard_manager) = context::state::<ShardManagerContainer>(data) {
+        shard_manager.lock().shutdown_all();
+    }
+}
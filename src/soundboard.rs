@@ -0,0 +1,224 @@
+//! Local library of sound clips, indexed from the `resources/sounds` folder.
+//!
+//! Clips in the folder itself can be played in every guild. Clips added with
+//! `sb add` go to a folder named after the guild, and only play there.
+
+use crate::audio::{self, Format};
+use serenity::model::id::GuildId;
+use serenity::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Biggest clip accepted through `.sb add`, Discord's default upload limit.
+pub const MAX_CLIP_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Longest name a clip can be registered under.
+pub const MAX_NAME_LEN: usize = 32;
+
+type Clips = BTreeMap<String, PathBuf>;
+
+pub struct Soundboard {
+    dir: PathBuf,
+    shared: Clips,
+    guilds: HashMap<GuildId, Clips>,
+}
+
+impl TypeMapKey for Soundboard {
+    type Value = Arc<Mutex<Soundboard>>;
+}
+
+impl Soundboard {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        let mut soundboard = Self {
+            dir: dir.into(),
+            shared: Clips::new(),
+            guilds: HashMap::new(),
+        };
+        soundboard.reload();
+
+        soundboard
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Re-scans the sounds folder, returning the amount of clips found.
+    pub fn reload(&mut self) -> usize {
+        self.shared = clips_in(&self.dir);
+        self.guilds.clear();
+
+        if let Ok(entries) = std::fs::read_dir(&self.dir) {
+            for path in entries.filter_map(Result::ok).map(|e| e.path()) {
+                let guild_id = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.parse::<u64>().ok());
+                if let (true, Some(guild_id)) = (path.is_dir(), guild_id) {
+                    self.guilds.insert(GuildId(guild_id), clips_in(&path));
+                }
+            }
+        }
+
+        let count = self.shared.len() + self.guilds.values().map(Clips::len).sum::<usize>();
+        info!("Soundboard loaded {} clips from {:?}", count, self.dir);
+
+        count
+    }
+
+    /// The clips a guild can play, all of them its own and the shared ones.
+    /// Outside guilds, only the shared ones.
+    pub fn names(&self, guild_id: Option<GuildId>) -> Vec<&str> {
+        let own = guild_id.and_then(|guild_id| self.guilds.get(&guild_id));
+        let names: BTreeSet<&str> = self
+            .shared
+            .keys()
+            .chain(own.into_iter().flat_map(Clips::keys))
+            .map(String::as_str)
+            .collect();
+
+        names.into_iter().collect()
+    }
+
+    /// A clip a guild can play, its own first.
+    pub fn get(&self, guild_id: GuildId, name: &str) -> Option<&Path> {
+        let name = name.to_lowercase();
+        self.guilds
+            .get(&guild_id)
+            .and_then(|clips| clips.get(&name))
+            .or_else(|| self.shared.get(&name))
+            .map(PathBuf::as_path)
+    }
+
+    /// Saves `data` as a new clip of a guild called `name`, keeping the
+    /// extension of `file_name`. The clip is only registered if it can be
+    /// decoded.
+    pub fn add(
+        &mut self,
+        guild_id: GuildId,
+        name: &str,
+        file_name: &str,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        let name = name.to_lowercase();
+        if !is_valid_name(&name) {
+            return Err(
+                "Clip names can only use letters, numbers, `-` and `_`, up to 32 characters.",
+            );
+        }
+
+        if self.get(guild_id, &name).is_some() {
+            return Err("A clip with that name already exists.");
+        }
+
+        if data.len() as u64 > MAX_CLIP_BYTES {
+            return Err("The clip is too big, max is 8MB.");
+        }
+
+        let format =
+            Format::from_path(file_name).ok_or("Only wav, ogg and mp3 files are supported.")?;
+
+        let dir = self.dir.join(guild_id.to_string());
+        if std::fs::create_dir_all(&dir).is_err() {
+            return Err("Unable to create the sounds folder.");
+        }
+
+        let path = dir.join(format!("{}.{}", name, format.extension()));
+        if std::fs::write(&path, data).is_err() {
+            return Err("Unable to save the clip.");
+        }
+
+        if let Err(e) = audio::file_to_pcm(&path) {
+            warn!("Rejected clip {:?}: {}", path, e);
+            let _ = std::fs::remove_file(&path);
+
+            return Err("Unable to decode the clip.");
+        }
+
+        self.guilds.entry(guild_id).or_default().insert(name, path);
+
+        Ok(())
+    }
+}
+
+/// The clips directly in `dir`, by lowercase name.
+fn clips_in(dir: &Path) -> Clips {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Unable to read sounds folder {:?}: {}", dir, e);
+
+            return Clips::new();
+        }
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|path| Format::from_path(path).is_some())
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?.to_lowercase();
+
+            Some((name, path))
+        })
+        .collect()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONE: &[u8] = include_bytes!("../resources/test/tone.ogg");
+
+    #[test]
+    fn keeps_added_clips_to_their_guild() {
+        let dir = std::env::temp_dir().join(format!("m-bot-sounds-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Horn.ogg"), TONE).unwrap();
+
+        let mut soundboard = Soundboard::new(&dir);
+        soundboard
+            .add(GuildId(1), "boom", "boom.ogg", TONE)
+            .unwrap();
+        assert_eq!(
+            soundboard.add(GuildId(1), "horn", "horn.ogg", TONE),
+            Err("A clip with that name already exists.")
+        );
+        assert_eq!(
+            soundboard.add(GuildId(1), "bad", "bad.ogg", b"not audio"),
+            Err("Unable to decode the clip.")
+        );
+
+        assert_eq!(soundboard.names(Some(GuildId(1))), vec!["boom", "horn"]);
+        assert_eq!(soundboard.names(Some(GuildId(2))), vec!["horn"]);
+        assert_eq!(soundboard.names(None), vec!["horn"]);
+        assert!(soundboard.get(GuildId(2), "boom").is_none());
+        assert!(soundboard.get(GuildId(2), "HORN").is_some());
+
+        // the same name is free in other guilds
+        soundboard
+            .add(GuildId(2), "boom", "boom.ogg", TONE)
+            .unwrap();
+        assert_ne!(
+            soundboard.get(GuildId(1), "boom"),
+            soundboard.get(GuildId(2), "boom")
+        );
+
+        assert_eq!(
+            Soundboard::new(&dir).names(Some(GuildId(1))),
+            vec!["boom", "horn"]
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
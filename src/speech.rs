@@ -0,0 +1,422 @@
+//! Voice commands, recognized by a local offline speech model.
+//!
+//! Listening is opt-in per guild with `listen on`. The speech of each user is
+//! downmixed to 16kHz mono and piped to their own instance of the recognizer
+//! set in `speech.command`, a local program printing a line per utterance (see
+//! `scripts/vosk-listen.py`), so no audio leaves the machine. Utterances with
+//! the wake word followed by a command, like "bot ready", run that command as
+//! the user who said it.
+
+use crate::commands::roll_call;
+use crate::config;
+use crate::context;
+
+use serenity::cache::Cache;
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const DEFAULT_WAKE_WORD: &str = "bot";
+
+/// Sample rate the recognizer is fed with.
+pub const SPEECH_RATE: u32 = 16_000;
+
+/// Recognizers running at once in a guild, one per user talking.
+const MAX_STREAMS_PER_GUILD: usize = 10;
+
+/// Packets queued for a recognizer that can't keep up, one second of audio.
+const BACKLOG: usize = 50;
+
+/// Discord sends nothing while a user is silent, but recognizers need some
+/// silence to tell an utterance ended. This much is fed after they stop.
+const SILENCE: Duration = Duration::from_millis(200);
+const MAX_SILENCE_CHUNKS: u32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoiceCommand {
+    Ready,
+    Status,
+    Cancel,
+}
+
+/// A command heard in a guild.
+pub struct Heard {
+    pub guild_id: GuildId,
+    /// Text channel `listen on` was used in, where answers go.
+    pub channel_id: ChannelId,
+    pub user_id: UserId,
+    pub command: VoiceCommand,
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|w| {
+            w.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// Finds the wake word, which can be several words, directly followed by a
+/// command in an utterance.
+pub fn parse_command(text: &str, wake: &[String]) -> Option<VoiceCommand> {
+    if wake.is_empty() {
+        return None;
+    }
+
+    words(text).windows(wake.len() + 1).find_map(|window| {
+        if window[..wake.len()] != *wake {
+            return None;
+        }
+
+        match window[wake.len()].as_str() {
+            "ready" => Some(VoiceCommand::Ready),
+            "status" => Some(VoiceCommand::Status),
+            "cancel" => Some(VoiceCommand::Cancel),
+            _ => None,
+        }
+    })
+}
+
+/// Downmixes the 48kHz voice packets to 16kHz mono, averaging each run of
+/// three frames.
+pub fn to_speech_rate(stereo: bool, data: &[i16]) -> Vec<i16> {
+    let channels = if stereo { 2 } else { 1 };
+    data.chunks(channels * 3)
+        .map(|frames| {
+            let sum: i32 = frames.iter().map(|s| i32::from(*s)).sum();
+            (sum / frames.len() as i32) as i16
+        })
+        .collect()
+}
+
+/// A recognizer process listening to a single user.
+struct Stream {
+    child: Child,
+    audio: SyncSender<Vec<i16>>,
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Listener {
+    channel_id: ChannelId,
+    streams: HashMap<UserId, Stream>,
+}
+
+pub struct SpeechManager {
+    command: Option<Vec<String>>,
+    wake: Vec<String>,
+    guilds: HashMap<GuildId, Listener>,
+    heard: Sender<Heard>,
+}
+
+impl TypeMapKey for SpeechManager {
+    type Value = Arc<Mutex<SpeechManager>>;
+}
+
+impl SpeechManager {
+    /// Returns the manager and the receiving end of the commands it hears,
+    /// see `spawn_dispatcher`.
+    pub fn new(command: Option<Vec<String>>, wake: &str) -> (Self, Receiver<Heard>) {
+        let (heard, commands) = mpsc::channel();
+        let manager = Self {
+            command: command.filter(|c| !c.is_empty()),
+            wake: words(wake),
+            guilds: HashMap::new(),
+            heard,
+        };
+
+        (manager, commands)
+    }
+
+    /// Uses the recognizer and wake word of the `speech` section of the
+    /// config.
+    pub fn from_config(speech: &config::Speech) -> (Self, Receiver<Heard>) {
+        Self::new(speech.command(), &speech.wake_word)
+    }
+
+    /// Switches to the recognizer and wake word of the `speech` section,
+    /// for the speakers heard from now on.
+    pub fn configure(&mut self, speech: &config::Speech) {
+        self.command = speech.command().filter(|c| !c.is_empty());
+        self.wake = words(&speech.wake_word);
+    }
+
+    pub fn wake_word(&self) -> String {
+        self.wake.join(" ")
+    }
+
+    pub fn start(&mut self, guild_id: GuildId, channel_id: ChannelId) -> Result<(), &'static str> {
+        if self.command.is_none() {
+            return Err("Voice commands aren't set up, `speech.command` is missing in the config.");
+        }
+
+        if self.wake.is_empty() {
+            return Err(
+                "Voice commands need a wake word, `speech.wake_word` is empty in the config.",
+            );
+        }
+
+        match self.guilds.get_mut(&guild_id) {
+            Some(listener) => listener.channel_id = channel_id,
+            None => {
+                self.guilds.insert(
+                    guild_id,
+                    Listener {
+                        channel_id,
+                        streams: HashMap::new(),
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops listening in a guild, returns whether it was.
+    pub fn stop(&mut self, guild_id: GuildId) -> bool {
+        self.guilds.remove(&guild_id).is_some()
+    }
+
+    pub fn is_listening(&self, guild_id: GuildId) -> bool {
+        self.guilds.contains_key(&guild_id)
+    }
+
+    /// Feeds a voice packet of a user to their recognizer, starting it first
+    /// if needed. A recognizer failing stops listening in the guild.
+    pub fn packet(&mut self, guild_id: GuildId, user_id: UserId, stereo: bool, data: &[i16]) {
+        let listener = match self.guilds.get_mut(&guild_id) {
+            Some(listener) => listener,
+            None => return,
+        };
+
+        if !listener.streams.contains_key(&user_id) {
+            if listener.streams.len() >= MAX_STREAMS_PER_GUILD {
+                return;
+            }
+
+            let command = self
+                .command
+                .as_ref()
+                .expect("listening without a recognizer");
+            let heard = self.heard.clone();
+            let wake = self.wake.clone();
+            match spawn_stream(command, guild_id, listener.channel_id, user_id, wake, heard) {
+                Ok(stream) => {
+                    debug!("[{}] Started speech recognizer for {}", guild_id, user_id);
+                    listener.streams.insert(user_id, stream);
+                }
+                Err(why) => {
+                    error!(
+                        "[{}] Unable to start the speech recognizer: {}",
+                        guild_id, why
+                    );
+                    self.guilds.remove(&guild_id);
+
+                    return;
+                }
+            }
+        }
+
+        let stream = &listener.streams[&user_id];
+        match stream.audio.try_send(to_speech_rate(stereo, data)) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                trace!("[{}] Speech recognizer of {} is behind", guild_id, user_id)
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("[{}] Speech recognizer of {} exited", guild_id, user_id);
+                self.guilds.remove(&guild_id);
+            }
+        }
+    }
+
+    /// Stops the recognizer of a user who left.
+    pub fn disconnect(&mut self, guild_id: GuildId, user_id: UserId) {
+        if let Some(listener) = self.guilds.get_mut(&guild_id) {
+            listener.streams.remove(&user_id);
+        }
+    }
+}
+
+/// Starts a recognizer process, with a thread writing it the audio and
+/// another sending the commands it heard.
+fn spawn_stream(
+    command: &[String],
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    wake: Vec<String>,
+    commands: Sender<Heard>,
+) -> std::io::Result<Stream> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let stdout = child.stdout.take().expect("piped stdout");
+
+    let (audio, samples) = mpsc::sync_channel::<Vec<i16>>(BACKLOG);
+    std::thread::spawn(move || {
+        let silence = vec![0i16; (SPEECH_RATE as u128 * SILENCE.as_millis() / 1000) as usize];
+        let mut silent_chunks = MAX_SILENCE_CHUNKS;
+        loop {
+            let chunk = match samples.recv_timeout(SILENCE) {
+                Ok(chunk) => {
+                    silent_chunks = 0;
+
+                    chunk
+                }
+                Err(RecvTimeoutError::Timeout) if silent_chunks < MAX_SILENCE_CHUNKS => {
+                    silent_chunks += 1;
+
+                    silence.clone()
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            let bytes: Vec<u8> = chunk.iter().flat_map(|s| s.to_le_bytes()).collect();
+            if stdin.write_all(&bytes).and_then(|_| stdin.flush()).is_err() {
+                break;
+            }
+        }
+    });
+
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            // The transcript isn't logged, only the commands found in it by
+            // the dispatcher.
+            if let Some(command) = parse_command(&line, &wake) {
+                let sent = commands.send(Heard {
+                    guild_id,
+                    channel_id,
+                    user_id,
+                    command,
+                });
+                if sent.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(Stream { child, audio })
+}
+
+/// Starts a thread running the commands heard, as the user who said them.
+pub fn spawn_dispatcher(
+    commands: Receiver<Heard>,
+    data: Arc<RwLock<ShareMap>>,
+    cache: Arc<RwLock<Cache>>,
+    http: Arc<Http>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        for heard in commands {
+            info!(
+                "[{}] Voice command {:?} by {}",
+                heard.guild_id, heard.command, heard.user_id
+            );
+
+            let result = match heard.command {
+                VoiceCommand::Ready => roll_call::join_roll_call(
+                    &data,
+                    &http,
+                    heard.guild_id,
+                    heard.channel_id,
+                    heard.user_id,
+                ),
+                VoiceCommand::Status => {
+                    roll_call::roll_call_status(&data, &http, heard.guild_id, heard.channel_id)
+                }
+                VoiceCommand::Cancel => roll_call::check_manager(
+                    &cache,
+                    &data,
+                    heard.guild_id,
+                    heard.user_id,
+                )
+                .and_then(|_| {
+                    roll_call::cancel_roll_call(&data, &http, heard.guild_id, heard.channel_id)
+                }),
+            };
+
+            if let Err(why) = result {
+                context::report(&http, heard.channel_id, heard.user_id, &why.to_string());
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wake(word: &str) -> Vec<String> {
+        words(word)
+    }
+
+    #[test]
+    fn parses_commands_after_the_wake_word() {
+        let bot = wake("bot");
+        assert_eq!(parse_command("bot ready", &bot), Some(VoiceCommand::Ready));
+        assert_eq!(
+            parse_command("okay Bot, status!", &bot),
+            Some(VoiceCommand::Status)
+        );
+        assert_eq!(
+            parse_command("um bot cancel it", &bot),
+            Some(VoiceCommand::Cancel)
+        );
+        assert_eq!(parse_command("ready bot", &bot), None);
+        assert_eq!(parse_command("robot ready", &bot), None);
+        assert_eq!(parse_command("bot please ready", &bot), None);
+        assert_eq!(parse_command("", &bot), None);
+    }
+
+    #[test]
+    fn wake_words_can_be_phrases() {
+        let hey = wake("Hey Bot");
+        assert_eq!(
+            parse_command("hey bot ready", &hey),
+            Some(VoiceCommand::Ready)
+        );
+        assert_eq!(parse_command("bot ready", &hey), None);
+        assert_eq!(parse_command("bot ready", &[]), None);
+    }
+
+    #[test]
+    fn downmixes_to_speech_rate() {
+        // one 20ms packet of 48kHz stereo is 320 samples at 16kHz mono
+        assert_eq!(to_speech_rate(true, &[0; 1920]).len(), 320);
+        assert_eq!(to_speech_rate(false, &[0; 960]).len(), 320);
+
+        assert_eq!(
+            to_speech_rate(true, &[100, 200, 300, 400, 500, 600]),
+            vec![350]
+        );
+        assert_eq!(
+            to_speech_rate(false, &[3, 6, 9, -30, -60, -90]),
+            vec![6, -60]
+        );
+        assert_eq!(to_speech_rate(true, &[i16::MAX; 6]), vec![i16::MAX]);
+    }
+}
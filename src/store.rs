@@ -0,0 +1,42 @@
+//! JSON files backing the state that survives restarts.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::Path;
+
+/// Reads `path`, falling back to the default value if it doesn't exist yet
+/// or can't be parsed.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Unable to read {:?}: {}", path, e);
+            }
+
+            return T::default();
+        }
+    };
+
+    serde_json::from_slice(&data).unwrap_or_else(|e| {
+        error!("Unable to parse {:?}, starting empty: {}", path, e);
+
+        T::default()
+    })
+}
+
+/// Writes `value` to `path` through a temporary file, so a crash halfway
+/// never leaves a truncated file behind.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> Result<(), &'static str> {
+    let data = serde_json::to_vec_pretty(value).map_err(|_| "Unable to serialize the data")?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|_| "Unable to create the data folder")?;
+    }
+
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data).map_err(|_| "Unable to write the data file")?;
+    std::fs::rename(&tmp, path).map_err(|_| "Unable to replace the data file")?;
+
+    Ok(())
+}
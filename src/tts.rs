@@ -1,142 +1,272 @@
+use crate::audio;
+use crate::config;
 use std::io::Read;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
 
+/// Raw 48 kHz stereo i16 PCM, ready to be played with `pcm(true, ...)`.
 pub type SpeechResponse = Box<dyn Read + Send + Sync>;
 
 pub trait TextToSpeech {
     fn get_speech(&mut self, text: &str) -> Result<SpeechResponse, &'static str>;
 }
 
+const NOT_CONFIGURED: &str = "Text to speech isn't configured.";
+/// Azure tokens last 10 minutes, renewed a minute early.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(9 * 60);
+
 pub struct VoiceRSS {
-    url: String,
+    /// `None` without a key configured.
+    url: Option<String>,
 }
 
 impl Default for VoiceRSS {
     fn default() -> Self {
-        let key = std::env::var("VOICERSS_TOKEN").expect("VOICERSS TOKEN");
-        let value = format!("http://api.voicerss.org/?key={}&c=wav&f=48khz_16bit_stereo&r=4&hl=en-us&b64=false&src=", key);
-        Self { url: value }
+        Self::with_voice(None, None)
+    }
+}
+
+/// Whether a voice name is one of Azure's, which start with their language
+/// like `en-US-GuyNeural`, rather than one of VoiceRSS like `Linda`.
+fn is_azure_voice(voice: &str) -> bool {
+    voice.matches('-').count() >= 2
+}
+
+impl VoiceRSS {
+    /// Speaks `language`, `en-us` by default, with a voice of it if given.
+    pub fn with_voice(language: Option<&str>, voice: Option<&str>) -> Self {
+        let config = config::current();
+        let voicerss = &config.tts.voicerss;
+        let url = voicerss.key.as_ref().map(|key| {
+            let mut query = form_urlencoded::Serializer::new(String::new());
+            query
+                .append_pair("key", key)
+                .append_pair("c", "wav")
+                .append_pair("f", "48khz_16bit_stereo")
+                .append_pair("r", "4")
+                .append_pair("hl", language.unwrap_or("en-us"));
+            if let Some(voice) = voice.filter(|voice| !is_azure_voice(voice)) {
+                query.append_pair("v", voice);
+            }
+            query.append_pair("b64", "false");
+
+            format!("{}?{}", voicerss.endpoint, query.finish())
+        });
+
+        Self { url }
+    }
+
+    /// The request for speaking `text`, which goes last in the query.
+    fn speech_url(&self, text: &str) -> Result<String, &'static str> {
+        let src: String = form_urlencoded::byte_serialize(text.as_bytes()).collect();
+
+        Ok(format!(
+            "{}&src={}",
+            self.url.as_ref().ok_or(NOT_CONFIGURED)?,
+            src
+        ))
     }
 }
 
 impl TextToSpeech for VoiceRSS {
     fn get_speech(&mut self, text: &str) -> Result<SpeechResponse, &'static str> {
-        let url = format!("{}{}", self.url, text);
+        let url = self.speech_url(text)?;
         match reqwest::blocking::get(url.as_str()) {
-            Ok(r) => Ok(Box::new(r)),
+            Ok(r) => to_speech_response(r),
             Err(_) => Err("Unable to make request"),
         }
     }
 }
 
 pub struct AzureTextToSpeech {
-    issue_token_url: String,
-    url: String,
-    token: Option<String>,
+    settings: config::Azure,
+    /// Like `en-US`.
+    language: String,
+    voice: String,
+    /// With the time it was issued at.
+    token: Option<(String, Instant)>,
     client: Option<reqwest::blocking::Client>,
 }
 
 impl Default for AzureTextToSpeech {
     fn default() -> Self {
-        let token_url =
-            std::env::var("AZURE_COGNITIVE_TOKEN_ENDPOINT").expect("AZURE TOKEN ENDPOINT");
+        Self::with_voice(None, None)
+    }
+}
 
-        let url = std::env::var("AZURE_COGNITIVE_TTS_ENDPOINT").expect("AZURE TOKEN ENDPOINT");
+impl AzureTextToSpeech {
+    /// Speaks `language`, `en-us` by default, with one of Azure's voices if
+    /// given, which should be one of that language.
+    pub fn with_voice(language: Option<&str>, voice: Option<&str>) -> Self {
+        let language = match language.unwrap_or("en-us").split_once('-') {
+            Some((language, region)) => format!("{}-{}", language, region.to_uppercase()),
+            None => String::from("en-US"),
+        };
+        let voice = match voice {
+            Some(voice) if is_azure_voice(voice) => voice.to_string(),
+            _ => String::from("en-US-Guy24kRUS"),
+        };
 
         Self {
-            issue_token_url: token_url,
-            url: url,
+            settings: config::current().tts.azure.clone(),
+            language,
+            voice,
             token: None,
             client: Some(reqwest::blocking::Client::new()),
         }
     }
-}
 
-impl AzureTextToSpeech {
-    fn get_client(&mut self) -> Option<reqwest::blocking::Client> {
-        if let None = self.token {
-            let key = std::env::var("AZURE_COGNITIVE_KEY").expect("AZURE COGNITIVE KEY");
+    /// The SSML document speaking `text`.
+    fn ssml(&self, text: &str) -> String {
+        format!(
+            "<speak version=\"1.0\" xmlns=\"https://www.w3.org/2001/10/synthesis\" xml:lang=\"{}\"><voice xml:lang='{}' name=\"{}\"><prosody rate=\"+20.00%\">{}</prosody></voice></speak>",
+            xml_escape(&self.language),
+            xml_escape(&self.language),
+            xml_escape(&self.voice),
+            xml_escape(text)
+        )
+    }
+
+    fn get_client(&mut self) -> Result<reqwest::blocking::Client, &'static str> {
+        let now = Instant::now();
+        if self
+            .token
+            .as_ref()
+            .is_none_or(|(_, issued)| expired(*issued, now))
+        {
+            let key = self.settings.key.clone().ok_or(NOT_CONFIGURED)?;
+            let issue_token_url = self.settings.token_endpoint.clone().ok_or(NOT_CONFIGURED)?;
             let c = reqwest::blocking::Client::new();
             let token = c
-                .post(self.issue_token_url.as_str())
+                .post(issue_token_url.as_str())
                 .header("Ocp-Apim-Subscription-Key", key)
                 .header("content-length", "0")
                 .send()
-                .unwrap()
-                .text()
-                .unwrap();
+                .and_then(|r| r.error_for_status())
+                .and_then(|r| r.text())
+                .map_err(|_| "Unable to issue token")?;
 
-            self.token = Some(token);
-            info!("TOKEN: {:?}", self.token);
+            self.token = Some((token, now));
+            debug!("Fetched an Azure token");
         };
 
-        self.client.clone()
+        self.client.clone().ok_or("No http client")
     }
-}
 
-impl TextToSpeech for AzureTextToSpeech {
-    fn get_speech(&mut self, _text: &str) -> Result<SpeechResponse, &'static str> {
-        let url = self.url.clone();
-        let client = self.get_client().unwrap();
-        let text = format!(
-            "<speak version=\"1.0\" xmlns=\"https://www.w3.org/2001/10/synthesis\" xml:lang=\"en-US\"><voice xml:lang='en-US' name=\"en-US-Guy24kRUS\"><prosody rate=\"+20.00%\">{}</prosody></voice></speak>", 
-            _text
-        );
+    /// Posts the `ssml` with the current token.
+    fn synthesize(
+        &self,
+        client: &reqwest::blocking::Client,
+        url: &str,
+        ssml: &str,
+    ) -> Result<reqwest::blocking::Response, &'static str> {
+        let (t, _) = self.token.clone().ok_or("No token")?;
+        let jwt = format!("{} {}", "Bearer", t);
 
-        let t = self.token.clone();
-        let jwt = format!("{} {}", "Bearer", t.unwrap());
-        info!("JWT HEADER: {}", jwt);
-
-        match client
-            .post(url.as_str())
+        client
+            .post(url)
             .header("Authorization", jwt)
             .header("Content-Type", "application/ssml+xml")
             .header("User-Agent", "cognitive-discord-rs")
             .header("X-Microsoft-OutputFormat", "riff-24khz-16bit-mono-pcm")
-            .body(text)
+            .body(ssml.to_string())
             .send()
-        {
-            Ok(r) => {
-                //let result = Ok(Box::new(r));
-
-                extern crate sample;
-                extern crate hound;
-
-                use hound::{WavReader, WavWriter};
-                use sample::{interpolate, ring_buffer, signal, Sample, Signal};
-                let reader = WavReader::new(r).unwrap();
-
-                // Get the wav spec and create a target with the new desired sample rate.
-                let spec = reader.spec();
-                info!("ORIGINAL SPEC: {:?}", spec);
-                let mut target = spec;
-                target.sample_rate = 48_000;
-                info!("TARGET SPEC: {:?}", target);
-                // Read the interleaved samples and convert them to a signal.
-                let samples = reader
-                    .into_samples()
-                    .filter_map(Result::ok)
-                    .map(i16::to_sample::<f64>);
-                let signal = signal::from_interleaved_samples_iter(samples);
-
-                // Convert the signal's sample rate using `Sinc` interpolation.
-                let ring_buffer = ring_buffer::Fixed::from([[0.0]; 100]);
-                let sinc = interpolate::Sinc::new(ring_buffer);
-                let new_signal =
-                    signal.from_hz_to_hz(sinc, spec.sample_rate as f64, target.sample_rate as f64);
-
-                // Write the result to an in memory buffer that implements std::io::Read.
-                let mut buffer = std::io::Cursor::new(Vec::new());
-                let mut writer = WavWriter::new(&mut buffer, target).unwrap();
-                for frame in new_signal.until_exhausted() {
-                    writer.write_sample(frame[0].to_sample::<i16>()).unwrap();
-                }
-
-                let _ = writer.finalize();
-
-                Ok(Box::new(buffer))
-            }
-            Err(_) => Err("Unable to make request"),
+            .map_err(|_| "Unable to make request")
+    }
+}
+
+/// Whether a token issued at `issued` is due for renewal at `now`.
+fn expired(issued: Instant, now: Instant) -> bool {
+    now.duration_since(issued) >= TOKEN_LIFETIME
+}
+
+impl TextToSpeech for AzureTextToSpeech {
+    fn get_speech(&mut self, text: &str) -> Result<SpeechResponse, &'static str> {
+        let url = self.settings.tts_endpoint.clone().ok_or(NOT_CONFIGURED)?;
+        let client = self.get_client()?;
+        let text = self.ssml(text);
+
+        let mut response = self.synthesize(&client, &url, &text)?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            debug!("The Azure token was refused, fetching a new one");
+            self.token = None;
+            let client = self.get_client()?;
+            response = self.synthesize(&client, &url, &text)?;
+        }
+
+        to_speech_response(response)
+    }
+}
+
+/// Converts a provider's WAV response into the format `SpeechResponse` promises,
+/// resampling as the response body arrives.
+fn to_speech_response<R>(wav: R) -> Result<SpeechResponse, &'static str>
+where
+    R: Read + Send + Sync + 'static,
+{
+    match audio::wav_stream(wav) {
+        Ok(stream) => Ok(Box::new(stream)),
+        Err(e) => {
+            error!("Unable to convert speech: {}", e);
+
+            Err("Unable to convert speech")
+        }
+    }
+}
+
+/// Escapes the characters with a meaning in XML, so spoken text can't break
+/// out of the SSML around it.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
         }
     }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_the_voicerss_text() {
+        let voicerss = VoiceRSS {
+            url: Some(String::from("http://api.voicerss.org/?key=k&b64=false")),
+        };
+
+        assert_eq!(
+            voicerss.speech_url("rally at 20:00 & bring #5?").unwrap(),
+            "http://api.voicerss.org/?key=k&b64=false&src=rally+at+20%3A00+%26+bring+%235%3F"
+        );
+        assert_eq!(VoiceRSS { url: None }.speech_url("hi"), Err(NOT_CONFIGURED));
+    }
+
+    #[test]
+    fn escapes_the_azure_text() {
+        let azure = AzureTextToSpeech::with_voice(Some("pt-pt"), Some("pt-PT-DuarteNeural"));
+        let ssml = azure.ssml("<break/> Tom & \"Jerry's\"");
+
+        assert!(ssml.contains(
+            "<prosody rate=\"+20.00%\">&lt;break/&gt; Tom &amp; &quot;Jerry&apos;s&quot;</prosody>"
+        ));
+        assert!(ssml.contains("xml:lang=\"pt-PT\""));
+        assert!(ssml.contains("name=\"pt-PT-DuarteNeural\""));
+    }
+    #[test]
+    fn renews_the_azure_token_before_it_expires() {
+        let issued = Instant::now();
+
+        assert!(!expired(issued, issued));
+        assert!(!expired(issued, issued + Duration::from_secs(8 * 60)));
+        assert!(expired(issued, issued + Duration::from_secs(9 * 60)));
+    }
 }